@@ -1,5 +1,6 @@
 from flask import Flask, request, render_template, redirect, url_for, flash
 import os
+import re
 
 app = Flask(__name__)
 app.secret_key = 'your_secret_key'
@@ -11,22 +12,41 @@ if DB_FILE:
 else:
     raise FileNotFoundError("No database file (.db) found in the current directory.")
 
+# First line of files whose values escape backslashes and line breaks
+ESCAPED = '#escaped'
+
+def is_escaped():
+    if not os.path.exists(DB_FILE):
+        return False
+    with open(DB_FILE, 'r') as f:
+        return f.readline().rstrip('\n') == ESCAPED
+
+def escape(value):
+    return value.replace('\\', '\\\\').replace('\n', '\\n').replace('\r', '\\r')
+
+def unescape(text):
+    return re.sub(r'\\(.?)', lambda m: {'\\': '\\', 'n': '\n', 'r': '\r'}.get(m.group(1), m.group(0)), text)
+
 def get_all_records():
     records = []
     if os.path.exists(DB_FILE):
+        escaped = is_escaped()
         with open(DB_FILE, 'r') as f:
             for line in f:
-                parts = line.strip().split(',')
+                # Only the first comma separates the key
+                parts = line.rstrip('\n').split(',', 1)
                 if len(parts) == 2:
                     try:
-                        records.append({'key': int(parts[0]), 'value': parts[1]})
+                        value = unescape(parts[1]) if escaped else parts[1]
+                        records.append({'key': int(parts[0]), 'value': value})
                     except ValueError:
                         pass
     return records
 
 def save_record(key, value):
+    escaped = is_escaped()
     with open(DB_FILE, 'a') as f:
-        f.write(f"{key},{value}\n")
+        f.write(f"{key},{escape(value) if escaped else value}\n")
 
 def delete_record(key):
     if not os.path.exists(DB_FILE):
@@ -36,8 +56,9 @@ def delete_record(key):
     if len(new_records) == len(records):
         return False
     with open(DB_FILE, 'w') as f:
+        f.write(ESCAPED + '\n')
         for r in new_records:
-            f.write(f"{r['key']},{r['value']}\n")
+            f.write(f"{r['key']},{escape(r['value'])}\n")
     return True
 
 def get_record(key):
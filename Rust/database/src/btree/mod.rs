@@ -1,4 +1,9 @@
-use std::io;
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
 
 #[derive(Debug, Clone)]
 pub struct Record {
@@ -6,15 +11,58 @@ pub struct Record {
     pub value: String,
 }
 
+// A single change to a key. `value` is None when the key was deleted.
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub version: u64,
+    pub timestamp: u64,
+    pub key: i32,
+    pub value: Option<String>,
+}
+
+// Point in the past to read a key at
+#[derive(Debug, Clone, Copy)]
+pub enum AsOf {
+    Time(u64),
+    Version(u64),
+}
+
+// The work done by a lookup, for EXPLAIN: the nodes visited on each level of
+// the tree (the root is level 0) and the key comparisons made
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Trace {
+    pub nodes_per_level: Vec<usize>,
+    pub comparisons: usize,
+}
+
+impl Trace {
+    fn visit(&mut self, depth: usize) {
+        if self.nodes_per_level.len() <= depth {
+            self.nodes_per_level.resize(depth + 1, 0);
+        }
+        self.nodes_per_level[depth] += 1;
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes_per_level.iter().sum()
+    }
+}
+
+// Every write to the tree, kept per key in the order they happened
+struct History {
+    next_version: u64,
+    versions: HashMap<i32, Vec<Version>>,
+}
+
 #[derive(Clone)]
-struct Node {
-    keys: Vec<i32>,
-    children: Vec<Node>,
-    values: Vec<String>,
+struct Node<K, V> {
+    keys: Vec<K>,
+    children: Vec<Node<K, V>>,
+    values: Vec<V>,
     is_leaf: bool,
 }
 
-impl Node {
+impl<K, V> Node<K, V> {
     fn new_leaf() -> Self {
         Node {
             keys: Vec::new(),
@@ -34,19 +82,30 @@ impl Node {
     }
 }
 
-pub struct BTree {
-    root: Node,
+// A B-Tree from keys to values. BTree keeps its records in one, secondary
+// indexes keep their entries in another.
+pub struct Tree<K, V> {
+    root: Node<K, V>,
+    len: usize,
 }
 
-impl BTree {
+impl<K: Ord + Clone, V: Clone> Tree<K, V> {
     pub fn new() -> Self {
-        BTree {
+        Tree {
             root: Node::new_leaf(),
+            len: 0,
         }
     }
 
-    pub fn insert(&mut self, key: i32, value: String) {
-        if let Some(split) = Self::insert_rec(&mut self.root, key, value) {
+    // Number of keys in the tree
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Insert or overwrite a key, returning the value it replaced
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut previous = None;
+        if let Some(split) = Self::insert_rec(&mut self.root, key, value, &mut previous) {
             let mut new_root = Node::new_internal();
             new_root.keys.push(split.key);
             new_root.values.push(split.value);
@@ -54,19 +113,28 @@ impl BTree {
             new_root.children.push(split.node);
             self.root = new_root;
         }
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
     }
 
-    fn insert_rec(node: &mut Node, key: i32, value: String) -> Option<SplitResult> {
+    fn insert_rec(
+        node: &mut Node<K, V>,
+        key: K,
+        value: V,
+        previous: &mut Option<V>,
+    ) -> Option<SplitResult<K, V>> {
         // Find the position to insert
         let pos = node
             .keys
             .iter()
-            .position(|&k| k >= key)
+            .position(|k| *k >= key)
             .unwrap_or(node.keys.len());
         
         // If we found the exact key, just update the value
         if pos < node.keys.len() && node.keys[pos] == key {
-            node.values[pos] = value;
+            *previous = Some(std::mem::replace(&mut node.values[pos], value));
             return None;
         }
         
@@ -76,7 +144,7 @@ impl BTree {
             node.values.insert(pos, value);
         } else {
             // Insert in internal node by recursively inserting into appropriate child
-            if let Some(split) = Self::insert_rec(&mut node.children[pos], key, value) {
+            if let Some(split) = Self::insert_rec(&mut node.children[pos], key, value, previous) {
                 node.keys.insert(pos, split.key);
                 node.values.insert(pos, split.value);
                 node.children.insert(pos + 1, split.node);
@@ -86,9 +154,9 @@ impl BTree {
         }
 
         // Check if node needs to be split
-        if node.keys.len() > ORDER - 1 {
+        if node.keys.len() > order() - 1 {
             let split_pos = node.keys.len() / 2;
-            let split_key = node.keys[split_pos];
+            let split_key = node.keys[split_pos].clone();
             let split_value = node.values[split_pos].clone();
 
             let mut new_node = if node.is_leaf {
@@ -120,19 +188,28 @@ impl BTree {
         }
     }
 
-    pub fn search(&self, key: i32) -> Option<String> {
-        Self::search_rec(&self.root, key)
+    pub fn search(&self, key: &K) -> Option<V> {
+        self.search_traced(key, &mut Trace::default())
     }
 
-    fn search_rec(node: &Node, key: i32) -> Option<String> {
+    // Search, recording the nodes visited and comparisons made in `trace`
+    pub fn search_traced(&self, key: &K, trace: &mut Trace) -> Option<V> {
+        Self::search_rec(&self.root, key, 0, trace)
+    }
+
+    fn search_rec(node: &Node<K, V>, key: &K, depth: usize, trace: &mut Trace) -> Option<V> {
+        trace.visit(depth);
         let pos = node
             .keys
             .iter()
-            .position(|&k| k >= key)
+            .position(|k| {
+                trace.comparisons += 1;
+                k >= key
+            })
             .unwrap_or(node.keys.len());
 
         // If we found the key
-        if pos < node.keys.len() && node.keys[pos] == key {
+        if pos < node.keys.len() && node.keys[pos] == *key {
             return Some(node.values[pos].clone());
         }
 
@@ -142,32 +219,33 @@ impl BTree {
         }
 
         // Search in the appropriate child
-        Self::search_rec(&node.children[pos], key)
+        Self::search_rec(&node.children[pos], key, depth + 1, trace)
     }
 
-    pub fn delete(&mut self, key: i32) -> bool {
+    // Remove a key, returning false if it was not there
+    pub fn delete(&mut self, key: &K) -> bool {
         let result = Self::delete_rec(&mut self.root, key);
         
         // If the root has no keys and is not a leaf, make its only child the new root
-        if self.root.keys.is_empty() && !self.root.is_leaf {
-            if !self.root.children.is_empty() {
-                self.root = self.root.children.remove(0);
-            }
+        if self.root.keys.is_empty() && !self.root.is_leaf && !self.root.children.is_empty() {
+            self.root = self.root.children.remove(0);
+        }
+        if result {
+            self.len -= 1;
         }
-        
         result
     }
 
-    fn delete_rec(node: &mut Node, key: i32) -> bool {
+    fn delete_rec(node: &mut Node<K, V>, key: &K) -> bool {
         // Find position of key or where it should be
         let pos = node
             .keys
             .iter()
-            .position(|&k| k >= key)
+            .position(|k| k >= key)
             .unwrap_or(node.keys.len());
 
         // Case 1: Key found in this node
-        if pos < node.keys.len() && node.keys[pos] == key {
+        if pos < node.keys.len() && node.keys[pos] == *key {
             if node.is_leaf {
                 // Simply remove key and value from leaf
                 node.keys.remove(pos);
@@ -182,10 +260,10 @@ impl BTree {
         // Case 2: Key not found in this node
         if node.is_leaf {
             // Key not in tree
-            return false;
+            false
         } else {
             // Try to delete from child
-            let min_keys = (ORDER - 1) / 2;
+            let min_keys = (order() - 1) / 2;
             let child_needs_rebalance = node.children[pos].keys.len() <= min_keys;
             
             // Ensure child has enough keys before recursing
@@ -193,61 +271,62 @@ impl BTree {
                 Self::ensure_child_has_min_keys(node, pos);
             }
             
-            // If the child at pos was merged, we need to adjust pos
-            let pos = if pos > 0 && pos >= node.children.len() {
-                pos - 1
-            } else {
-                pos
-            };
+            // Borrowing and merging move keys between this node and its
+            // children, so find the child holding the key again
+            let pos = node
+                .keys
+                .iter()
+                .position(|k| k >= key)
+                .unwrap_or(node.keys.len());
             
             Self::delete_rec(&mut node.children[pos], key)
         }
     }
     
-    fn delete_from_internal_node(node: &mut Node, pos: usize) -> bool {
-        let key = node.keys[pos];
+    fn delete_from_internal_node(node: &mut Node<K, V>, pos: usize) -> bool {
+        let key = node.keys[pos].clone();
         
         // Case 1: If predecessor child has at least min_keys + 1 keys, replace with predecessor
-        if node.children[pos].keys.len() > (ORDER - 1) / 2 {
+        if node.children[pos].keys.len() > (order() - 1) / 2 {
             let (pred_key, pred_value) = Self::get_predecessor(&mut node.children[pos]);
-            node.keys[pos] = pred_key;
+            node.keys[pos] = pred_key.clone();
             node.values[pos] = pred_value;
-            return Self::delete_rec(&mut node.children[pos], pred_key);
+            return Self::delete_rec(&mut node.children[pos], &pred_key);
         }
         
         // Case 2: If successor child has at least min_keys + 1 keys, replace with successor
-        if node.children[pos + 1].keys.len() > (ORDER - 1) / 2 {
+        if node.children[pos + 1].keys.len() > (order() - 1) / 2 {
             let (succ_key, succ_value) = Self::get_successor(&mut node.children[pos + 1]);
-            node.keys[pos] = succ_key;
+            node.keys[pos] = succ_key.clone();
             node.values[pos] = succ_value;
-            return Self::delete_rec(&mut node.children[pos + 1], succ_key);
+            return Self::delete_rec(&mut node.children[pos + 1], &succ_key);
         }
         
         // Case 3: If both children have min_keys, merge them and delete
         Self::merge_children(node, pos);
-        return Self::delete_rec(&mut node.children[pos], key);
+        Self::delete_rec(&mut node.children[pos], &key)
     }
     
-    fn get_predecessor(node: &mut Node) -> (i32, String) {
+    fn get_predecessor(node: &mut Node<K, V>) -> (K, V) {
         let mut current = node;
         while !current.is_leaf {
             let last_idx = current.children.len() - 1;
             current = &mut current.children[last_idx];
         }
         let last_idx = current.keys.len() - 1;
-        (current.keys[last_idx], current.values[last_idx].clone())
+        (current.keys[last_idx].clone(), current.values[last_idx].clone())
     }
     
-    fn get_successor(node: &mut Node) -> (i32, String) {
+    fn get_successor(node: &mut Node<K, V>) -> (K, V) {
         let mut current = node;
         while !current.is_leaf {
             current = &mut current.children[0];
         }
-        (current.keys[0], current.values[0].clone())
+        (current.keys[0].clone(), current.values[0].clone())
     }
     
-    fn ensure_child_has_min_keys(node: &mut Node, child_pos: usize) {
-        let min_keys = (ORDER - 1) / 2;
+    fn ensure_child_has_min_keys(node: &mut Node<K, V>, child_pos: usize) {
+        let min_keys = (order() - 1) / 2;
         
         // Try to borrow from left sibling
         if child_pos > 0 {
@@ -277,9 +356,9 @@ impl BTree {
         }
     }
     
-    fn borrow_from_left(node: &mut Node, child_pos: usize) {
+    fn borrow_from_left(node: &mut Node<K, V>, child_pos: usize) {
         // Get parent key/value
-        let parent_key = node.keys[child_pos - 1];
+        let parent_key = node.keys[child_pos - 1].clone();
         let parent_value = node.values[child_pos - 1].clone();
         
         // Use split_at_mut to get mutable references to both children
@@ -303,9 +382,9 @@ impl BTree {
         node.values[child_pos - 1] = left.values.remove(last_idx);
     }
     
-    fn borrow_from_right(node: &mut Node, child_pos: usize) {
+    fn borrow_from_right(node: &mut Node<K, V>, child_pos: usize) {
         // Get parent key/value
-        let parent_key = node.keys[child_pos];
+        let parent_key = node.keys[child_pos].clone();
         let parent_value = node.values[child_pos].clone();
         
         // Use split_at_mut to get mutable references to both children
@@ -328,9 +407,9 @@ impl BTree {
         node.values[child_pos] = right.values.remove(0);
     }
     
-    fn merge_children(node: &mut Node, left_pos: usize) {
+    fn merge_children(node: &mut Node<K, V>, left_pos: usize) {
         // Get parent key/value to be merged down
-        let parent_key = node.keys[left_pos];
+        let parent_key = node.keys[left_pos].clone();
         let parent_value = node.values[left_pos].clone();
         
         // Clone the right node before removing it
@@ -358,36 +437,402 @@ impl BTree {
         }
     }
 
-    pub fn get_all_records(&self) -> Vec<Record> {
-        let mut records = Vec::new();
-        Self::collect_records(&self.root, &mut records);
-        records
+    // Every entry in key order
+    pub fn entries(&self) -> Vec<(K, V)> {
+        let mut entries = Vec::new();
+        Self::collect_entries(&self.root, &mut entries);
+        entries
     }
     
-    fn collect_records(node: &Node, records: &mut Vec<Record>) {
+    fn collect_entries(node: &Node<K, V>, entries: &mut Vec<(K, V)>) {
         for i in 0..node.keys.len() {
             if !node.is_leaf {
-                Self::collect_records(&node.children[i], records);
+                Self::collect_entries(&node.children[i], entries);
             }
-            records.push(Record {
-                key: node.keys[i],
-                value: node.values[i].clone(),
-            });
+            entries.push((node.keys[i].clone(), node.values[i].clone()));
         }
         
         // Process the last child for internal nodes
         if !node.is_leaf && !node.children.is_empty() {
-            Self::collect_records(&node.children[node.children.len() - 1], records);
+            Self::collect_entries(&node.children[node.children.len() - 1], entries);
         }
     }
 
-    
+    // Entries with keys in the given range, in key order. Only the subtrees
+    // that can hold keys in the range are visited.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        self.range_traced(range, &mut Trace::default())
+    }
+
+    // Range, recording the nodes visited and comparisons made in `trace`
+    pub fn range_traced<R: RangeBounds<K>>(&self, range: R, trace: &mut Trace) -> Vec<(K, V)> {
+        let mut entries = Vec::new();
+        let bounds = (range.start_bound(), range.end_bound());
+        Self::range_rec(&self.root, bounds, 0, trace, &mut |key, value| {
+            entries.push((key.clone(), value.clone()))
+        });
+        entries
+    }
+
+    // Number of keys in the given range, counted without copying entries
+    pub fn count<R: RangeBounds<K>>(&self, range: R) -> usize {
+        let mut count = 0;
+        let bounds = (range.start_bound(), range.end_bound());
+        Self::range_rec(&self.root, bounds, 0, &mut Trace::default(), &mut |_, _| count += 1);
+        count
+    }
+
+    // Keys of each node, level by level from the root
+    pub fn levels(&self) -> Vec<Vec<Vec<K>>> {
+        let mut levels = Vec::new();
+        let mut nodes = vec![&self.root];
+        while !nodes.is_empty() {
+            levels.push(nodes.iter().map(|node| node.keys.clone()).collect());
+            nodes = nodes.iter().flat_map(|node| node.children.iter()).collect();
+        }
+        levels
+    }
+
+    // Smallest entry, found by following the leftmost path to a leaf
+    pub fn first(&self) -> Option<(K, V)> {
+        let mut node = &self.root;
+        while !node.is_leaf {
+            node = node.children.first()?;
+        }
+        Some((node.keys.first()?.clone(), node.values.first()?.clone()))
+    }
+
+    // Largest entry, found by following the rightmost path to a leaf
+    pub fn last(&self) -> Option<(K, V)> {
+        let mut node = &self.root;
+        while !node.is_leaf {
+            node = node.children.last()?;
+        }
+        Some((node.keys.last()?.clone(), node.values.last()?.clone()))
+    }
+
+    // Calls `visit` with each entry in the range. Returns false once a key
+    // past the end of the range has been reached.
+    fn range_rec<F: FnMut(&K, &V)>(
+        node: &Node<K, V>,
+        (start, end): (Bound<&K>, Bound<&K>),
+        depth: usize,
+        trace: &mut Trace,
+        visit: &mut F,
+    ) -> bool {
+        trace.visit(depth);
+        for i in 0..node.keys.len() {
+            let key = &node.keys[i];
+            trace.comparisons += usize::from(start != Bound::Unbounded) + usize::from(end != Bound::Unbounded);
+
+            // The child left of this key only holds smaller keys
+            let after_start = match start {
+                Bound::Included(s) => key >= s,
+                Bound::Excluded(s) => key > s,
+                Bound::Unbounded => true,
+            };
+            let child_may_match = match start {
+                Bound::Included(s) | Bound::Excluded(s) => key > s,
+                Bound::Unbounded => true,
+            };
+            if !node.is_leaf
+                && child_may_match
+                && !Self::range_rec(&node.children[i], (start, end), depth + 1, trace, visit)
+            {
+                return false;
+            }
+
+            let before_end = match end {
+                Bound::Included(e) => key <= e,
+                Bound::Excluded(e) => key < e,
+                Bound::Unbounded => true,
+            };
+            if !before_end {
+                return false;
+            }
+            if after_start {
+                visit(key, &node.values[i]);
+            }
+        }
+
+        // Process the last child for internal nodes
+        if !node.is_leaf && !node.children.is_empty() {
+            let last = &node.children[node.children.len() - 1];
+            return Self::range_rec(last, (start, end), depth + 1, trace, visit);
+        }
+        true
+    }
+}
+
+pub struct BTree {
+    tree: Tree<i32, String>,
+    history: Option<History>,
+    // Time (seconds since the epoch) at which a key stops being visible
+    expiry: HashMap<i32, u64>,
 }
 
-const ORDER: usize = 4;
+impl BTree {
+    pub fn new() -> Self {
+        BTree {
+            tree: Tree::new(),
+            history: None,
+            expiry: HashMap::new(),
+        }
+    }
+
+    // Insert or overwrite a key, returning the value it replaced. Any expiry
+    // time set on the key is cleared.
+    pub fn insert(&mut self, key: i32, value: String) -> Option<String> {
+        let expired = self.is_expired(key);
+        self.expiry.remove(&key);
+
+        if self.history.is_some() {
+            self.record_version(key, Some(value.clone()));
+        }
+
+        self.tree.insert(key, value).filter(|_| !expired)
+    }
+
+    pub fn search(&self, key: i32) -> Option<String> {
+        self.search_traced(key, &mut Trace::default())
+    }
 
-struct SplitResult {
-    key: i32,
-    value: String,
-    node: Node,
-}
\ No newline at end of file
+    pub fn search_traced(&self, key: i32, trace: &mut Trace) -> Option<String> {
+        if self.is_expired(key) {
+            return None;
+        }
+        self.tree.search_traced(&key, trace)
+    }
+
+    // Number of keys stored, including expired ones not yet removed
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    // Keys of each node, level by level from the root
+    pub fn levels(&self) -> Vec<Vec<Vec<i32>>> {
+        self.tree.levels()
+    }
+
+    // Remove a key, returning false if it was not there or had expired
+    pub fn delete(&mut self, key: i32) -> bool {
+        let expired = self.is_expired(key);
+        self.expiry.remove(&key);
+        let result = self.tree.delete(&key);
+
+        if result && self.history.is_some() {
+            self.record_version(key, None);
+        }
+        
+        result && !expired
+    }
+
+    pub fn get_all_records(&self) -> Vec<Record> {
+        self.visible(self.tree.entries())
+    }
+
+    // Records with keys in the given range, in key order
+    pub fn range<R: RangeBounds<i32>>(&self, range: R) -> Vec<Record> {
+        self.visible(self.tree.range(range))
+    }
+
+    pub fn range_traced<R: RangeBounds<i32>>(&self, range: R, trace: &mut Trace) -> Vec<Record> {
+        self.visible(self.tree.range_traced(range, trace))
+    }
+
+    // Number of records with keys in the given range
+    pub fn count<R: RangeBounds<i32>>(&self, range: R) -> usize {
+        let expired = self
+            .expiry
+            .keys()
+            .filter(|key| range.contains(key) && self.is_expired(**key))
+            .count();
+        self.tree.count(range) - expired
+    }
+
+    // Record with the smallest key
+    pub fn first(&self) -> Option<Record> {
+        match self.tree.first() {
+            Some((key, _)) if self.is_expired(key) => self.get_all_records().into_iter().next(),
+            entry => entry.map(|(key, value)| Record { key, value }),
+        }
+    }
+
+    // Record with the largest key
+    pub fn last(&self) -> Option<Record> {
+        match self.tree.last() {
+            Some((key, _)) if self.is_expired(key) => self.get_all_records().pop(),
+            entry => entry.map(|(key, value)| Record { key, value }),
+        }
+    }
+
+    // Turn entries into records, leaving out expired keys
+    fn visible(&self, entries: Vec<(i32, String)>) -> Vec<Record> {
+        entries
+            .into_iter()
+            .filter(|(key, _)| self.expiry.is_empty() || !self.is_expired(*key))
+            .map(|(key, value)| Record { key, value })
+            .collect()
+    }
+
+    // Hide the key from reads once the given time has passed. Does nothing if
+    // the key does not exist.
+    pub fn expire_at(&mut self, key: i32, expires_at: u64) {
+        if self.tree.search(&key).is_some() {
+            self.expiry.insert(key, expires_at);
+        }
+    }
+
+    // When the key expires, if it has an expiry, passed or not
+    pub fn expires_at(&self, key: i32) -> Option<u64> {
+        self.expiry.get(&key).copied()
+    }
+
+    pub fn is_expired(&self, key: i32) -> bool {
+        self.expiry.get(&key).is_some_and(|&at| at <= now())
+    }
+
+    // Keys that have expired but are still stored in the tree
+    pub fn expired_keys(&self) -> Vec<i32> {
+        let now = now();
+        let mut keys: Vec<i32> = self
+            .expiry
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(&key, _)| key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // Expiry times of keys that are still visible, ordered by key
+    pub fn expiries(&self) -> Vec<(i32, u64)> {
+        let now = now();
+        let mut expiries: Vec<(i32, u64)> = self
+            .expiry
+            .iter()
+            .filter(|(_, &at)| at > now)
+            .map(|(&key, &at)| (key, at))
+            .collect();
+        expiries.sort();
+        expiries
+    }
+
+    pub fn history_enabled(&self) -> bool {
+        self.history.is_some()
+    }
+
+    // Start keeping versions. Existing records are captured as the first
+    // version of each key so that "as of" reads have a starting point.
+    pub fn enable_history(&mut self) {
+        if self.history.is_some() {
+            return;
+        }
+
+        self.history = Some(History {
+            next_version: 1,
+            versions: HashMap::new(),
+        });
+        for record in self.get_all_records() {
+            self.record_version(record.key, Some(record.value));
+        }
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    // Replace the history with versions loaded from disk
+    pub fn restore_history(&mut self, versions: Vec<Version>) {
+        let mut history = History {
+            next_version: 1,
+            versions: HashMap::new(),
+        };
+        for version in versions {
+            history.next_version = history.next_version.max(version.version + 1);
+            history.versions.entry(version.key).or_default().push(version);
+        }
+        for entries in history.versions.values_mut() {
+            entries.sort_by_key(|v| v.version);
+        }
+        self.history = Some(history);
+    }
+
+    pub fn history(&self, key: i32) -> Vec<Version> {
+        match &self.history {
+            Some(history) => history.versions.get(&key).cloned().unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    // All versions of all keys, ordered by version number
+    pub fn all_versions(&self) -> Vec<Version> {
+        let mut versions: Vec<Version> = match &self.history {
+            Some(history) => history.versions.values().flatten().cloned().collect(),
+            None => Vec::new(),
+        };
+        versions.sort_by_key(|v| v.version);
+        versions
+    }
+
+    // Read the value a key had at the given time or version. Returns None if
+    // the key did not exist then or history is not enabled.
+    pub fn search_as_of(&self, key: i32, as_of: AsOf) -> Option<String> {
+        let history = self.history.as_ref()?;
+        let versions = history.versions.get(&key)?;
+
+        versions
+            .iter()
+            .rev()
+            .find(|v| match as_of {
+                AsOf::Time(timestamp) => v.timestamp <= timestamp,
+                AsOf::Version(version) => v.version <= version,
+            })
+            .and_then(|v| v.value.clone())
+    }
+
+    fn record_version(&mut self, key: i32, value: Option<String>) {
+        if let Some(history) = self.history.as_mut() {
+            let version = Version {
+                version: history.next_version,
+                timestamp: now(),
+                key,
+                value,
+            };
+            history.next_version += 1;
+            history.versions.entry(key).or_default().push(version);
+        }
+    }
+}
+
+// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Largest number of children of a node unless --order says otherwise
+pub const DEFAULT_ORDER: usize = 4;
+
+// Smallest order at which a split node still has a key on each side
+pub const MIN_ORDER: usize = 3;
+
+// The order of every tree. It is set once at startup, before any tree is built,
+// since a tree's nodes would not fit a different one.
+static ORDER: AtomicUsize = AtomicUsize::new(DEFAULT_ORDER);
+
+pub fn order() -> usize {
+    ORDER.load(Ordering::Relaxed)
+}
+
+pub fn set_order(order: usize) {
+    ORDER.store(order.max(MIN_ORDER), Ordering::Relaxed);
+}
+
+struct SplitResult<K, V> {
+    key: K,
+    value: V,
+    node: Node<K, V>,
+}
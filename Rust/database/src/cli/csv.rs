@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+
+use crate::query::Value;
+
+// A field as CSV: quoted when it holds a comma, a quote or a line break, with
+// its quotes doubled
+pub fn escape(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+// One line of CSV, without the line break
+pub fn row<S: AsRef<str>>(fields: &[S]) -> String {
+    let fields: Vec<Cow<str>> = fields.iter().map(|field| escape(field.as_ref())).collect();
+    fields.join(",")
+}
+
+// One line of CSV for a row of cells. NULL is an empty field and empty text
+// is quoted, so that the two can be told apart.
+pub fn cells(row: &[Value]) -> String {
+    let fields: Vec<Cow<str>> = row
+        .iter()
+        .map(|cell| match cell {
+            Value::Null => Cow::Borrowed(""),
+            Value::Text(text) if text.is_empty() => Cow::Borrowed("\"\""),
+            cell => Cow::Owned(escape(&cell.to_string()).into_owned()),
+        })
+        .collect();
+    fields.join(",")
+}
+
+// Split CSV text into rows of fields, None for an empty field without quotes.
+// A quoted field can hold commas, doubled quotes and line breaks. Empty lines
+// are skipped.
+pub fn parse(text: &str) -> Result<Vec<Vec<Option<String>>>, String> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    // The field as read, None if it is empty and was not quoted
+    let take = |field: &mut String, quoted: &mut bool| {
+        let field = std::mem::take(field);
+        (std::mem::take(quoted) || !field.is_empty()).then_some(field)
+    };
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                quoted = true;
+                let start = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("Line {}: unterminated quote", start)),
+                    }
+                }
+            }
+            ',' => fields.push(take(&mut field, &mut quoted)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                if !fields.is_empty() || !field.is_empty() || quoted {
+                    fields.push(take(&mut field, &mut quoted));
+                    rows.push(std::mem::take(&mut fields));
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if !fields.is_empty() || !field.is_empty() || quoted {
+        fields.push(take(&mut field, &mut quoted));
+        rows.push(fields);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        assert_eq!(row(&["1", "ann", ""]), "1,ann,");
+    }
+
+    #[test]
+    fn fields_with_commas_quotes_and_line_breaks_are_quoted() {
+        assert_eq!(escape("a,b"), "\"a,b\"");
+        assert_eq!(escape(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn rows_survive_a_round_trip() {
+        let rows = [
+            vec![Value::Int(1), Value::Text("a, b".to_string()), Value::Text(r#"it's "quoted""#.to_string())],
+            vec![Value::Int(2), Value::Text("two\r\nlines".to_string()), Value::Text(String::new())],
+            vec![Value::Int(3), Value::Null, Value::Text("NULL".to_string())],
+        ];
+        let text: Vec<String> = rows.iter().map(|row| cells(row)).collect();
+        assert_eq!(text[2], "3,,NULL");
+
+        let field = |text: &str| Some(text.to_string());
+        assert_eq!(
+            parse(&text.join("\n")).unwrap(),
+            [
+                vec![field("1"), field("a, b"), field(r#"it's "quoted""#)],
+                vec![field("2"), field("two\r\nlines"), field("")],
+                vec![field("3"), None, field("NULL")],
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_and_empty_lines() {
+        let field = |text: &str| Some(text.to_string());
+        let rows = [vec![field("1"), field("a")], vec![field("2"), field("b")], vec![field("")]];
+        assert_eq!(parse("1,a\r\n\r\n2,b\r\n\"\"\n").unwrap(), rows);
+    }
+
+    #[test]
+    fn unterminated_quote_fails_with_its_line() {
+        assert_eq!(parse("1,a\n2,\"b\nc").unwrap_err(), "Line 2: unterminated quote");
+    }
+}
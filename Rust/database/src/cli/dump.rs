@@ -0,0 +1,141 @@
+use crate::btree::{self, now, Record};
+use crate::db::Database;
+use crate::query::Value;
+use crate::schema::Schema;
+
+// The commands that rebuild a database, one per line: its schema and indexes,
+// its records in key order, then each table the same way. History is turned on
+// last so that replaying the records does not add versions; the versions kept
+// before the dump are not part of it.
+pub fn script(db: &Database, db_name: &str) -> String {
+    let mut lines = vec![
+        format!("-- Dump of {}.db, B-Tree order {}", db_name, btree::order()),
+        "-- Replay it into an empty database with .restore <file> or --script <file>".to_string(),
+    ];
+
+    if let Some(schema) = db.schema() {
+        lines.push(format!("schema {}", schema));
+    }
+    lines.extend(table_lines(db, None));
+
+    for name in db.table_names() {
+        let Some(table) = db.table(&name) else { continue };
+        match table.schema() {
+            Some(schema) => lines.push(format!("CREATE TABLE {} ({})", name, schema)),
+            None => lines.push(format!("CREATE TABLE {}", name)),
+        }
+        lines.extend(table_lines(table, Some(&name)));
+    }
+
+    if db.tree().history_enabled() {
+        lines.push("history on".to_string());
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+// The indexes and records of the main table (None) or of a named table
+fn table_lines(table: &Database, name: Option<&str>) -> Vec<String> {
+    let on = name.map(|name| format!("{} ", name)).unwrap_or_default();
+    let into = name.map(|name| format!("INTO {} ", name)).unwrap_or_default();
+
+    let mut lines: Vec<String> = table
+        .indexes()
+        .iter()
+        .map(|index| format!("CREATE INDEX ON {}({})", on, index.column()))
+        .collect();
+
+    let expiries = table.tree().expiries();
+    let now = now();
+    for record in table.tree().get_all_records() {
+        let literals: Vec<String> = cells(table.schema(), &record).iter().map(literal).collect();
+        let mut line = format!("INSERT {}VALUES ({}, {})", into, record.key, literals.join(", "));
+        if let Some((_, at)) = expiries.iter().find(|(key, _)| *key == record.key) {
+            line.push_str(&format!(" TTL {}", at.saturating_sub(now).max(1)));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+// The cells of a record: the columns of a row, or the value as text
+fn cells(schema: Option<&Schema>, record: &Record) -> Vec<Value> {
+    match schema.map(|schema| schema.decode(&record.value)) {
+        Some(Ok(cells)) => cells,
+        _ => vec![Value::Text(record.value.clone())],
+    }
+}
+
+// A cell as a query literal, e.g. 'it''s', 1.0 or NULL. Floats are written
+// without an exponent, which queries cannot read.
+fn literal(cell: &Value) -> String {
+    match cell {
+        Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        Value::Float(number) => {
+            let digits = number.to_string();
+            if digits.contains('.') {
+                digits
+            } else {
+                digits + ".0"
+            }
+        }
+        cell => cell.to_string(),
+    }
+}
+
+// Whether a database holds nothing yet, so that a dump can be restored into it
+pub fn is_empty(db: &Database) -> bool {
+    db.tree().len() == 0 && db.schema().is_none() && db.indexes().is_empty() && db.table_names().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::cli::{run_line, run_script, Session};
+    use crate::config::Config;
+
+    #[test]
+    fn floats_have_no_exponent() {
+        assert_eq!(literal(&Value::Float(1e20)), "100000000000000000000.0");
+        assert_eq!(literal(&Value::Float(1e-7)), "0.0000001");
+        assert_eq!(literal(&Value::Float(-2.5)), "-2.5");
+        assert_eq!(literal(&Value::Text("it's".to_string())), "'it''s'");
+    }
+
+    #[test]
+    fn a_restored_dump_dumps_the_same() {
+        let dir = std::env::temp_dir().join(format!("ryou-dump-{}", std::process::id()));
+        let config = Config::parse_from(["database", "--data-dir", &dir.to_string_lossy()]);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let setup = "\
+schema name TEXT(32), score FLOAT NULL, ok BOOL
+CREATE INDEX ON (score)
+INSERT VALUES (1, 'it''s (big)', 100000000000000000000.0, true), (2, 'tiny', 0.0000001, false)
+INSERT VALUES (3, 'line\nbreak', NULL, true) TTL 3600
+CREATE TABLE notes
+INSERT INTO notes VALUES (7, 'expiring') TTL 600
+INSERT INTO notes VALUES (8, 'NULL')
+history on
+";
+        let mut original = Session::new(&config, "original").unwrap();
+        run_script(setup.as_bytes(), |line| run_line(&mut original, line)).unwrap();
+        let dumped = script(&original.db, "db");
+
+        let mut restored = Session::new(&config, "restored").unwrap();
+        assert!(is_empty(&restored.db));
+        run_script(dumped.as_bytes(), |line| run_line(&mut restored, line)).unwrap();
+        let redumped = script(&restored.db, "db");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(dumped.contains("INSERT VALUES (1, 'it''s (big)', 100000000000000000000.0, true)"));
+        assert!(dumped.contains("INSERT INTO notes VALUES (7, 'expiring') TTL "));
+        // The TTLs may have ticked down a second in between
+        let without_ttl = |dump: &str| -> Vec<String> {
+            dump.lines().map(|line| line.split(" TTL ").next().unwrap_or_default().to_string()).collect()
+        };
+        assert_eq!(without_ttl(&dumped), without_ttl(&redumped));
+    }
+}
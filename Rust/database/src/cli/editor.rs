@@ -0,0 +1,215 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+
+use super::{is_query, meta};
+
+// Prompt for each command, as in the C version
+pub const PROMPT: &str = "db > ";
+
+// File in the home directory keeping the commands of earlier sessions
+const HISTORY_FILE: &str = ".ryou_history";
+
+const HISTORY_SIZE: usize = 1000;
+
+// Words a command can start with
+const COMMANDS: [&str; 23] = [
+    "insert", "update", "upsert", "select", "delete", "search", "history", "schema", "tables", "explain",
+    "analyze", "copy", "undo", "redo", "exit", "SELECT", "INSERT", "UPSERT", "UPDATE", "DELETE", "CREATE", "DROP", "EXPLAIN",
+];
+
+pub type LineEditor = Editor<ReplHelper, DefaultHistory>;
+
+// What the words of a line are completed with
+#[derive(Clone, Copy, PartialEq)]
+pub enum Completion {
+    // Names of the databases in the data directory
+    Database,
+    // Commands and meta-commands
+    Command,
+}
+
+pub struct ReplHelper {
+    pub completion: Completion,
+    // Where the databases offered for completion are
+    data_dir: PathBuf,
+}
+
+// An editor with arrow-key editing, completion and the history of earlier
+// sessions
+pub fn new_editor(data_dir: &Path) -> io::Result<LineEditor> {
+    let config = Config::builder()
+        .max_history_size(HISTORY_SIZE)
+        .map_err(readline_error)?
+        .history_ignore_dups(true)
+        .map_err(readline_error)?
+        .auto_add_history(false)
+        .completion_type(CompletionType::List)
+        .build();
+
+    let mut editor = LineEditor::with_config(config).map_err(readline_error)?;
+    editor.set_helper(Some(ReplHelper {
+        completion: Completion::Database,
+        data_dir: data_dir.to_path_buf(),
+    }));
+    if let Some(path) = history_path() {
+        // There is no history before the first session
+        let _ = editor.load_history(&path);
+    }
+    Ok(editor)
+}
+
+pub fn save_history(editor: &mut LineEditor) {
+    if let Some(path) = history_path() {
+        if let Err(e) = editor.save_history(&path) {
+            eprintln!("Failed to save history to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::home_dir().map(|home| home.join(HISTORY_FILE))
+}
+
+pub fn readline_error(error: ReadlineError) -> io::Error {
+    match error {
+        ReadlineError::Io(error) => error,
+        error => io::Error::other(error),
+    }
+}
+
+// Names of the databases in a directory, e.g. "mydb" for mydb.db
+pub fn database_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_suffix(".db").map(|name| name.to_string())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+// Whether the input holds a whole command. A query goes on over several lines
+// until its quotes and parentheses are closed.
+pub fn is_complete(input: &str) -> bool {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let is_statement = match words.as_slice() {
+        [first, second, ..] => is_query(first) && second.parse::<i32>().is_err(),
+        _ => false,
+    };
+    if !is_statement {
+        return true;
+    }
+
+    let mut quote = None;
+    let mut depth = 0;
+    for c in input.chars() {
+        match quote {
+            // A doubled quote closes and reopens the string
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            },
+        }
+    }
+    quote.is_none() && depth <= 0
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+
+        let candidates: Vec<String> = match self.completion {
+            Completion::Database => database_names(&self.data_dir),
+            Completion::Command if start == 0 => {
+                let meta_commands = meta::COMMANDS.iter().map(|(name, _, _)| name);
+                COMMANDS.iter().chain(meta_commands).map(|c| c.to_string()).collect()
+            }
+            // The database after ".open" or ".attach"
+            Completion::Command => match line[..start].split_whitespace().collect::<Vec<_>>().as_slice() {
+                [".open" | ".attach"] => database_names(&self.data_dir),
+                _ => Vec::new(),
+            },
+        };
+        let pairs = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_complete(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(completion: Completion, dir: &Path, line: &str) -> Vec<String> {
+        let helper = ReplHelper {
+            completion,
+            data_dir: dir.to_path_buf(),
+        };
+        let history = DefaultHistory::new();
+        let (_, pairs) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+        pairs.into_iter().map(|pair| pair.replacement).collect()
+    }
+
+    #[test]
+    fn completes_commands_and_database_names() {
+        let dir = std::env::temp_dir().join(format!("ryou-editor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["users.db", "orders.db", "users.ttl"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let names = |line| complete(Completion::Command, &dir, line);
+        assert_eq!(complete(Completion::Database, &dir, "u"), ["users"]);
+        assert_eq!(names("up"), ["update", "upsert"]);
+        assert_eq!(names(".open "), ["orders", "users"]);
+        assert_eq!(names(".attach o"), ["orders"]);
+        assert!(names(".attach orders as ").is_empty());
+        assert!(names("select ").is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
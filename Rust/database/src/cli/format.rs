@@ -0,0 +1,142 @@
+use clap::ValueEnum;
+
+use crate::query::Value;
+
+use super::csv;
+
+// How records and query results are printed
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Mode {
+    // Cells separated by " | "
+    List,
+    // Columns aligned in an ASCII table
+    Table,
+    // Comma-separated values under a header line
+    Csv,
+    // An array of objects from column names to values
+    Json,
+    // An object per line
+    Ndjson,
+    // A "column = value" line per cell, records separated by blank lines
+    Line,
+}
+
+impl Mode {
+    pub fn parse(name: &str) -> Option<Mode> {
+        Mode::from_str(name, true).ok()
+    }
+
+    pub fn name(self) -> String {
+        self.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+    }
+
+    pub fn names() -> Vec<String> {
+        Mode::value_variants().iter().map(|mode| mode.name()).collect()
+    }
+}
+
+// Print rows under their column names
+pub fn print_rows(mode: Mode, columns: &[String], rows: &[Vec<Value>]) {
+    match mode {
+        Mode::List => {
+            if rows.is_empty() {
+                println!("No records found");
+                return;
+            }
+            println!("{}", columns.join(" | "));
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|cell| cell.to_string()).collect();
+                println!("{}", cells.join(" | "));
+            }
+            println!("{}", row_count(rows.len()));
+        }
+        Mode::Table => print_table(columns, rows),
+        Mode::Csv => {
+            println!("{}", csv::row(columns));
+            for row in rows {
+                println!("{}", csv::cells(row));
+            }
+        }
+        Mode::Json => {
+            let objects: Vec<serde_json::Value> = rows.iter().map(|row| object(columns, row)).collect();
+            println!("{}", serde_json::to_string_pretty(&objects).unwrap_or_default());
+        }
+        Mode::Ndjson => {
+            for row in rows {
+                println!("{}", object(columns, row));
+            }
+        }
+        Mode::Line => {
+            let width = columns.iter().map(|column| column.chars().count()).max().unwrap_or(0);
+            for (index, row) in rows.iter().enumerate() {
+                if index > 0 {
+                    println!();
+                }
+                for (column, cell) in columns.iter().zip(row) {
+                    println!("{:>width$} = {}", column, cell, width = width);
+                }
+            }
+        }
+    }
+}
+
+fn object(columns: &[String], row: &[Value]) -> serde_json::Value {
+    let fields = columns
+        .iter()
+        .zip(row)
+        .map(|(column, cell)| (column.clone(), serde_json::to_value(cell).unwrap_or_default()))
+        .collect();
+    serde_json::Value::Object(fields)
+}
+
+// e.g.
+// +-----+-------+
+// | key | value |
+// +-----+-------+
+// |   1 | one   |
+// +-----+-------+
+fn print_table(columns: &[String], rows: &[Vec<Value>]) {
+    // Line breaks and tabs would break the columns apart
+    let text = |cell: &Value| cell.to_string().replace('\n', "\\n").replace('\t', "\\t").replace('\r', "\\r");
+    let cells: Vec<Vec<String>> = rows.iter().map(|row| row.iter().map(text).collect()).collect();
+
+    let mut widths: Vec<usize> = columns.iter().map(|column| column.chars().count()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let border: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+    let border = format!("+{}+", border.join("+"));
+
+    println!("{}", border);
+    let header: Vec<String> = columns
+        .iter()
+        .zip(&widths)
+        .map(|(column, width)| format!("{:<width$}", column, width = width))
+        .collect();
+    println!("| {} |", header.join(" | "));
+    println!("{}", border);
+    for (row, texts) in rows.iter().zip(&cells) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(texts)
+            .zip(&widths)
+            .map(|((cell, text), width)| match cell {
+                // Numbers line up on the right
+                Value::Int(_) | Value::Float(_) => format!("{:>width$}", text, width = width),
+                _ => format!("{:<width$}", text, width = width),
+            })
+            .collect();
+        println!("| {} |", line.join(" | "));
+    }
+    if !rows.is_empty() {
+        println!("{}", border);
+    }
+    println!("{}", row_count(rows.len()));
+}
+
+// e.g. "(1 row)" or "(3 rows)"
+fn row_count(count: usize) -> String {
+    format!("({} row{})", count, if count == 1 { "" } else { "s" })
+}
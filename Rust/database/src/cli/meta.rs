@@ -0,0 +1,322 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+
+use crate::btree;
+use crate::db::{Database, Write, WriteMode, MAIN_TABLE};
+use crate::query::Value;
+use crate::schema::{ColumnType, Schema};
+
+use super::words::Word;
+use super::{csv, describe_table, dump, editor, open_database, print_help, record_table};
+use super::{run_line, run_script, Attached, Flow, Mode, Session};
+
+// Commands starting with a dot, which control the session rather than the
+// data: the name, how it is typed and what it does
+pub const COMMANDS: [(&str, &str, &str); 15] = [
+    (".help", ".help", "Show this list"),
+    (".open", ".open <name>", "Close this database and open another"),
+    (".attach", ".attach <name> AS <alias>", "Open another database next to this one"),
+    (".detach", ".detach <alias>", "Close an attached database"),
+    (".databases", ".databases", "List the databases in the data directory"),
+    (".dump", ".dump [file]", "Write the commands that rebuild the database"),
+    (".restore", ".restore <file>", "Rebuild an empty database from a .dump"),
+    (".import", ".import <file> [table]", "Insert or replace the records of a CSV file"),
+    (".export", ".export <file> [table]", "Write the records to a CSV file"),
+    (".stats", ".stats", "Show the size and shape of the database"),
+    (".btree", ".btree [table]", "Print the keys of each node of the B-Tree"),
+    (".timer", ".timer on|off", "Show how long each command takes"),
+    (".mode", ".mode [list|table|csv|...]", "Show or set the output: list, table, csv, json, ndjson or line"),
+    (".read", ".read <file>", "Run the commands in a file"),
+    (".exit", "exit, .exit", "Quit the program"),
+];
+
+pub fn run(session: &mut Session, words: &[Word]) -> Result<Flow, String> {
+    let parts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+
+    match parts.as_slice() {
+        [".exit"] => return Ok(Flow::Exit),
+        [".help"] => print_help(),
+        [".open", db_name] => {
+            // Its lock is still held by this session
+            if *db_name == session.db_name {
+                return Err(format!("Database '{}' is already open", db_name));
+            }
+            if let Some(alias) = attached_as(session, db_name) {
+                return Err(format!("Database '{}' is attached as '{}', .detach it first", db_name, alias));
+            }
+            if session.attached.contains_key(*db_name) {
+                return Err(format!("The name '{}' is already taken by an attached database", db_name));
+            }
+            session.db = open_database(session.config, db_name).map_err(|e| e.to_string())?;
+            session.undo.watch(&mut session.db);
+            session.db_name = db_name.to_string();
+            println!("Using database: {}", session.describe());
+        }
+        [".attach", db_name, as_word, alias] if as_word.eq_ignore_ascii_case("as") => {
+            attach(session, db_name, alias)?
+        }
+        [".attach", db_name] => attach(session, db_name, db_name)?,
+        [".detach", alias] => match session.attached.remove(*alias) {
+            Some(attached) => println!("Detached {}.db", attached.db_name),
+            None => return Err(format!("No database is attached as '{}'", alias)),
+        },
+        [".databases"] => {
+            for name in editor::database_names(&session.config.data_dir) {
+                let state = if name == session.db_name {
+                    " (open)".to_string()
+                } else if let Some(alias) = attached_as(session, &name) {
+                    format!(" (attached as {})", alias)
+                } else {
+                    String::new()
+                };
+                println!("- {}{}", name, state);
+            }
+        }
+        [".dump"] => print!("{}", dump::script(&session.db, &session.db_name)),
+        [".dump", path] => {
+            let script = dump::script(&session.db, &session.db_name);
+            fs::write(path, script).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            println!("Dumped {} to {}", session.describe(), path);
+        }
+        [".restore", path] => {
+            let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            if !dump::is_empty(&session.db) {
+                return Err(format!("Database '{}' is not empty, .open a new one to restore into", session.db_name));
+            }
+            let reader = BufReader::new(file);
+            let flow = run_script(reader, |line| run_line(session, line)).map_err(|e| format!("{}: {}", path, e))?;
+            println!("Restored {} from {}", session.describe(), path);
+            return Ok(flow);
+        }
+        [".import", path, table @ ..] if table.len() <= 1 => {
+            let table = table_mut(&mut session.db, table.first().copied())?;
+            let count = import(table, path)?;
+            println!("Imported {} records from {}", count, path);
+        }
+        [".export", path, table @ ..] if table.len() <= 1 => {
+            let table = table_mut(&mut session.db, table.first().copied())?;
+            let (header, rows) = record_table(table.schema(), &table.tree().get_all_records());
+            let mut text = csv::row(&header) + "\n";
+            for row in &rows {
+                text.push_str(&csv::cells(row));
+                text.push('\n');
+            }
+            fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            println!("Exported {} records to {}", rows.len(), path);
+        }
+        [".stats"] => print_stats(session),
+        [".btree", table @ ..] if table.len() <= 1 => {
+            let table = table_mut(&mut session.db, table.first().copied())?;
+            for (level, nodes) in table.tree().levels().iter().enumerate() {
+                let nodes: Vec<String> = nodes
+                    .iter()
+                    .map(|keys| {
+                        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+                        format!("[{}]", keys.join(" "))
+                    })
+                    .collect();
+                println!("level {}: {}", level, nodes.join(" "));
+            }
+        }
+        [".timer", setting @ ("on" | "off")] => session.timer = *setting == "on",
+        [".mode", name @ ..] if name.len() <= 1 => set_mode(&mut session.mode, name.first().copied())?,
+        [".read", path] => {
+            let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let reader = BufReader::new(file);
+            return run_script(reader, |line| run_line(session, line)).map_err(|e| format!("{}: {}", path, e));
+        }
+        [command, ..] => return usage(command),
+        [] => {}
+    }
+    Ok(Flow::Continue)
+}
+
+// Open a database next to the session's own, its records then being typed as
+// <alias>.<key>
+fn attach(session: &mut Session, db_name: &str, alias: &str) -> Result<(), String> {
+    if db_name == session.db_name {
+        return Err(format!("Database '{}' is already open", db_name));
+    }
+    if let Some(alias) = attached_as(session, db_name) {
+        return Err(format!("Database '{}' is already attached as '{}'", db_name, alias));
+    }
+    if alias == session.db_name || session.attached.contains_key(alias) {
+        return Err(format!("The name '{}' is already taken", alias));
+    }
+    if alias.is_empty() || !alias.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("Invalid alias '{}', use letters, digits and _", alias));
+    }
+
+    let mut db = open_database(session.config, db_name).map_err(|e| e.to_string())?;
+    session.undo.watch(&mut db);
+    let read_only = if db.is_read_only() { " (read-only)" } else { "" };
+    println!("Attached {}.db as {}{}", db_name, alias, read_only);
+    session.attached.insert(alias.to_string(), Attached { db_name: db_name.to_string(), db });
+    Ok(())
+}
+
+// The alias of a database if it is attached
+fn attached_as<'s>(session: &'s Session, db_name: &str) -> Option<&'s str> {
+    session
+        .attached
+        .iter()
+        .find(|(_, attached)| attached.db_name == db_name)
+        .map(|(alias, _)| alias.as_str())
+}
+
+// Print the mode, or change it to the one named
+pub fn set_mode(mode: &mut Mode, name: Option<&str>) -> Result<(), String> {
+    match name.map(|name| (name, Mode::parse(name))) {
+        None => println!("Mode: {}", mode.name()),
+        Some((_, Some(new_mode))) => *mode = new_mode,
+        Some((name, None)) => {
+            return Err(format!("Unknown mode '{}', expected one of {}", name, Mode::names().join(", ")))
+        }
+    }
+    Ok(())
+}
+
+// Usage of a meta-command typed with the wrong arguments, or the list of them
+// for one that does not exist
+pub fn usage(command: &str) -> Result<Flow, String> {
+    match COMMANDS.iter().find(|(name, _, _)| *name == command) {
+        Some((_, synopsis, _)) => Err(format!("Usage: {}", synopsis)),
+        None => {
+            let names: Vec<&str> = COMMANDS.iter().map(|(name, _, _)| *name).collect();
+            println!("Unknown command '{}', expected one of {}", command, names.join(" "));
+            Ok(Flow::Continue)
+        }
+    }
+}
+
+// The main table, or the table of that name
+fn table_mut<'a>(db: &'a mut Database, name: Option<&str>) -> Result<&'a mut Database, String> {
+    let name = name.unwrap_or(MAIN_TABLE);
+    db.table_mut(name).ok_or_else(|| format!("Table '{}' not found", name))
+}
+
+// Upsert the records of a CSV file written by .export: a key and a value, or a
+// key and the columns of a row, an empty field without quotes being NULL. A
+// header line is skipped. Nothing is written if any record is invalid.
+fn import(db: &mut Database, path: &str) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let rows = csv::parse(&text)?;
+    let has_header = rows.first().is_some_and(|row| key(row).is_err());
+
+    let mut writes = Vec::new();
+    for (number, row) in rows.iter().enumerate().skip(has_header as usize) {
+        let invalid = |e: String| format!("Record {}: {}", number + 1, e);
+        let key = key(row).map_err(invalid)?;
+        let cells = &row[1..];
+        let value = match (db.schema(), cells) {
+            (Some(schema), cells) => import_row(schema, cells).and_then(|row| schema.encode(&row)),
+            (None, [value]) => Ok(value.clone().unwrap_or_default()),
+            (None, cells) => Err(format!("Expected 1 value but got {}", cells.len())),
+        };
+        writes.push(Write::Put {
+            key,
+            value: value.map_err(invalid)?,
+            mode: WriteMode::Upsert,
+            expires_at: None,
+        });
+    }
+    db.write_all(writes).map_err(|e| e.to_string())
+}
+
+// The key in the first field of an imported row
+fn key(row: &[Option<String>]) -> Result<i32, String> {
+    let field = row[0].as_deref().unwrap_or_default();
+    field.parse::<i32>().map_err(|_| format!("invalid key '{}'", field))
+}
+
+// The cells of an imported row. Text is taken as it is, so that the text
+// "NULL" stays text.
+fn import_row(schema: &Schema, fields: &[Option<String>]) -> Result<Vec<Value>, String> {
+    if fields.len() != schema.columns.len() {
+        return Err(format!(
+            "Expected {} values ({}) but got {}",
+            schema.columns.len(),
+            schema.names().join(", "),
+            fields.len()
+        ));
+    }
+    fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match (field, &schema.columns[index].ty) {
+            (None, _) => Ok(Value::Null),
+            (Some(text), ColumnType::Text(_)) => Ok(Value::Text(text.clone())),
+            (Some(text), _) => schema.parse_cell(index, text),
+        })
+        .collect()
+}
+
+fn print_stats(session: &Session) {
+    let db = &session.db;
+    let path = session.config.database_path(&session.db_name);
+    let size = fs::metadata(&path).map(|metadata| metadata.len());
+    let size = size.map_or_else(|e: io::Error| e.to_string(), |size| format!("{} bytes", size));
+    println!("Database:  {} ({})", session.describe(), size);
+    println!("File:      {}", path);
+
+    let levels = db.tree().levels();
+    let nodes: usize = levels.iter().map(Vec::len).sum();
+    println!("Records:   {} ({} expiring)", db.tree().len(), db.tree().expiries().len());
+    println!(
+        "B-Tree:    order {}, height {}, {} nodes, {:.1} keys per node",
+        btree::order(),
+        levels.len(),
+        nodes,
+        db.tree().len() as f64 / nodes.max(1) as f64
+    );
+    if db.tree().history_enabled() {
+        println!("History:   on, {} versions", db.tree().all_versions().len());
+    } else {
+        println!("History:   off");
+    }
+
+    let mut names = vec![MAIN_TABLE.to_string()];
+    names.extend(db.table_names());
+    println!("Tables:");
+    for name in names {
+        if let Some(table) = db.table(&name) {
+            println!("- {}", describe_table(&name, table));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::cli::{run_line, Session};
+    use crate::config::Config;
+
+    #[test]
+    fn null_and_empty_text_survive_an_export() {
+        let dir = std::env::temp_dir().join(format!("ryou-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config::parse_from(["database", "--data-dir", &dir.to_string_lossy()]);
+        let file = dir.join("rows.csv").to_string_lossy().into_owned();
+        let schema = "schema name TEXT(16) NULL, age INT NULL";
+
+        let mut original = Session::new(&config, "original").unwrap();
+        let insert = "INSERT VALUES (1, 'NULL', NULL), (2, NULL, 3), (3, '', NULL), (4, 'a, \"b\"', 5)";
+        for line in [schema, insert] {
+            run_line(&mut original, line).unwrap();
+        }
+        run_line(&mut original, &format!(".export {}", file)).unwrap();
+
+        let mut imported = Session::new(&config, "imported").unwrap();
+        run_line(&mut imported, schema).unwrap();
+        run_line(&mut imported, &format!(".import {}", file)).unwrap();
+        let exported = std::fs::read_to_string(&file).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(exported, "key,name,age\n1,NULL,\n2,,3\n3,\"\",\n4,\"a, \"\"b\"\"\",5\n");
+        let records = |session: &Session| -> Vec<(i32, String)> {
+            session.db.tree().get_all_records().into_iter().map(|r| (r.key, r.value)).collect()
+        };
+        assert_eq!(records(&imported), records(&original));
+    }
+}
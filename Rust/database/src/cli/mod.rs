@@ -1,98 +1,718 @@
-use std::io;
+mod csv;
+mod dump;
+mod editor;
+mod format;
+mod meta;
+mod remote;
+mod subcommand;
+mod undo;
+mod words;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 use std::time::{Instant, Duration};
 use std::collections::HashMap;
 
-use crate::btree::BTree;
-use crate::storage::{load_records, save_records};
+use rustyline::error::ReadlineError;
+
+use crate::btree::{self, AsOf, BTree, Trace};
+use crate::config::Config;
+use crate::db::{Database, WriteMode, MAIN_TABLE};
+use crate::query::{self, Explain, QueryOutput, Statement, Value};
+use crate::schema::Schema;
+use crate::storage::load_records;
 use crate::btree::Record;
+use editor::{Completion, LineEditor, PROMPT};
+use remote::Remote;
+use undo::UndoStack;
+
+pub use format::Mode;
+pub use subcommand::run as run_subcommand;
+
+pub fn start_cli(config: &Config) -> io::Result<()> {
+    // Read the --script file before opening the database, which creates it
+    let script = match &config.script {
+        Some(path) => {
+            let file = File::open(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)))?;
+            Some(BufReader::new(file))
+        }
+        None => None,
+    };
+    let mut editor = editor::new_editor(&config.data_dir)?;
 
-pub fn start_cli() -> io::Result<()> {
-    println!("Enter database name:");
-    let mut db_name = String::new();
-    io::stdin().read_line(&mut db_name)?;
+    // Ask for the database unless --db names it
+    let db_name = match &config.db {
+        Some(db_name) => db_name.clone(),
+        None => {
+            println!("Enter database name:");
+            match editor.readline("") {
+                Ok(line) => line,
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Ok(()),
+                Err(e) => return Err(editor::readline_error(e)),
+            }
+        }
+    };
     let db_name = db_name.trim();
-    let file_path = format!("{}.db", db_name);
 
-    let records = load_records(&file_path)?;
-    let mut tree = BTree::new();
+    // With --remote the server holds the database and the commands go to it
+    if let Some(url) = &config.remote {
+        let mut remote = Remote::connect(url, db_name, config)?;
+        println!("Using database: {}", remote.describe());
+        if let Some(script) = script {
+            return run_script_file(script, |input| remote.run_line(input));
+        }
+        print_help();
+        return repl(&mut editor, |input| remote.run_line(input));
+    }
 
-    for record in records {
-        tree.insert(record.key, record.value);
+    let mut session = Session::new(config, db_name)?;
+    println!("Using database: {}", session.describe());
+    if let Some(script) = script {
+        return run_script_file(script, |input| run_line(&mut session, input));
     }
+    println!("B-Tree Database (Order {})", btree::order());
+    print_help();
+    repl(&mut editor, |input| run_line(&mut session, input))
+}
 
-    println!("Using database: {}", file_path);
-    println!("B-Tree Database (Order 4)");
-    println!("Commands:");
-    println!("  insert <key> <value>  - Insert a new record");
-    println!("  select                - List all records");
-    println!("  select <key>          - Find specific record");
-    println!("  delete <key>          - Delete a record");
-    println!("  analyze <key>         - Compare search performance across data structures");
-    println!("  exit                  - Quit the program");
+// Read commands until exit or the end of input, running each with `run`
+fn repl(editor: &mut LineEditor, mut run: impl FnMut(&str) -> Result<Flow, String>) -> io::Result<()> {
+    if let Some(helper) = editor.helper_mut() {
+        helper.completion = Completion::Command;
+    }
 
     loop {
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
-
-        match parts.as_slice() {
-            ["insert", key, value] => {
-                if let Ok(key) = key.parse::<i32>() {
-                    tree.insert(key, value.to_string());
-                    save_records(&file_path, &tree.get_all_records())?;
-                    println!("Inserted: {} => {}", key, value);
+        let input = match editor.readline(PROMPT) {
+            Ok(input) => input,
+            // Ctrl-C drops the line being typed
+            Err(ReadlineError::Interrupted) => continue,
+            // End of input, release the database like "exit" would
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                editor::save_history(editor);
+                return Err(editor::readline_error(e));
+            }
+        };
+        if input.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(input.as_str()).map_err(editor::readline_error)?;
+        match run(&input) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => break,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    editor::save_history(editor);
+    Ok(())
+}
+
+// Run the --script file instead of the prompt, exiting with 1 at the first
+// command that fails
+fn run_script_file(script: impl BufRead, run: impl FnMut(&str) -> Result<Flow, String>) -> io::Result<()> {
+    if let Err(e) = run_script(script, run) {
+        eprintln!("{}", e);
+        std::process::exit(subcommand::EXIT_FAILURE);
+    }
+    Ok(())
+}
+
+// Open <db_name>.db in the data directory, falling back to read-only access if
+// another process has it open
+fn open_database(config: &Config, db_name: &str) -> io::Result<Database> {
+    let file_path = config.database_path(db_name);
+    match Database::open(&file_path, config.read_only) {
+        Ok(db) => Ok(db),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+            eprintln!("{}, opening read-only", e);
+            Database::open(&file_path, true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// What the session does after a command
+enum Flow {
+    Continue,
+    Exit,
+}
+
+// The open database and the settings of a CLI session
+struct Session<'a> {
+    config: &'a Config,
+    db_name: String,
+    db: Database,
+    // Databases opened with .attach, by alias
+    attached: HashMap<String, Attached>,
+    // Whether each command prints how long it took
+    timer: bool,
+    mode: Mode,
+    // The commands that changed records, to undo and redo
+    undo: UndoStack,
+    // Number of commands running, more than one while .read runs a file
+    depth: usize,
+}
+
+// A database kept open next to the session's own
+struct Attached {
+    db_name: String,
+    db: Database,
+}
+
+impl<'a> Session<'a> {
+    fn new(config: &'a Config, db_name: &str) -> io::Result<Self> {
+        let mut session = Session {
+            config,
+            db_name: db_name.to_string(),
+            db: open_database(config, db_name)?,
+            attached: HashMap::new(),
+            timer: false,
+            mode: config.format.unwrap_or(Mode::List),
+            undo: UndoStack::default(),
+            depth: 0,
+        };
+        session.undo.watch(&mut session.db);
+        Ok(session)
+    }
+
+    // e.g. "mydb.db (read-only)"
+    fn describe(&self) -> String {
+        format!("{}.db{}", self.db_name, if self.db.is_read_only() { " (read-only)" } else { "" })
+    }
+
+    // The open database under its name, or an attached one under its alias
+    fn database(&self, name: &str) -> Option<&Database> {
+        if name == self.db_name {
+            return Some(&self.db);
+        }
+        self.attached.get(name).map(|attached| &attached.db)
+    }
+
+    fn database_mut(&mut self, name: &str) -> Option<&mut Database> {
+        if name == self.db_name {
+            return Some(&mut self.db);
+        }
+        self.attached.get_mut(name).map(|attached| &mut attached.db)
+    }
+
+    // Pick up edits made by other programs to any of the databases
+    fn reload(&mut self) {
+        let databases = std::iter::once((&self.db_name, &mut self.db))
+            .chain(self.attached.iter_mut().map(|(alias, attached)| (alias, &mut attached.db)));
+        for (name, db) in databases {
+            match db.reload_if_changed() {
+                Ok(true) => println!("Database file of '{}' was changed on disk, reloaded it", name),
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to reload database '{}': {}", name, e),
+            }
+        }
+    }
+
+    // A database and key typed as <key> or <database>.<key>, the database
+    // being the open one by name or an attached one by alias
+    fn record(&self, word: &str) -> Result<(String, i32), String> {
+        let (name, key) = match word.split_once('.') {
+            Some((name, key)) => (name, key),
+            None => (self.db_name.as_str(), word),
+        };
+        let key = key.parse::<i32>().map_err(|_| format!("Invalid key '{}'", key))?;
+        match self.database(name) {
+            Some(_) => Ok((name.to_string(), key)),
+            None => Err(format!("No database is open or attached as '{}'", name)),
+        }
+    }
+}
+
+// Run one command line against the database, printing what it finds or
+// changes. A command that fails returns its error message.
+fn run_line(session: &mut Session, input: &str) -> Result<Flow, String> {
+    let words = words::split(input)?;
+
+    // Pick up edits made by other programs before running the command. They
+    // are not the session's to undo.
+    let journaled = session.undo.journaled();
+    session.reload();
+    session.undo.forget(journaled);
+
+    let start = Instant::now();
+    session.depth += 1;
+    let result = match words.first() {
+        Some(word) if !word.quoted && word.text.starts_with('.') => meta::run(session, &words),
+        _ => run_command(session, input, &words),
+    };
+    session.depth -= 1;
+    if session.timer {
+        println!("Run time: {:.6}s", start.elapsed().as_secs_f64());
+    }
+
+    // Even a failed command may have changed records, e.g. a .read that
+    // stopped halfway. Undo and redo are not undone themselves, and a file run
+    // with .read is undone as a whole.
+    if session.depth == 0 {
+        if matches!(words.as_slice(), [word] if word.is_bare("undo") || word.is_bare("redo")) {
+            session.undo.forget(0);
+        } else {
+            undo::record(session, input);
+        }
+    }
+    result
+}
+
+fn run_command(session: &mut Session, input: &str, words: &[words::Word]) -> Result<Flow, String> {
+    let mut parts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+    let mode = session.mode;
+
+    match parts.as_slice() {
+        ["copy", from, "->", to] | ["copy", from, to] => return copy(session, from, to),
+        ["copy", ..] => return Err("Usage: copy [<database>.]<key> -> [<database>.]<key>".to_string()),
+        ["undo"] => return undo::undo(session).map(|_| Flow::Continue),
+        ["redo"] => return undo::redo(session).map(|_| Flow::Continue),
+        _ => {}
+    }
+
+    // A join can read the attached databases, e.g. SELECT * FROM users JOIN a.users
+    if parts.first().is_some_and(|first| is_query(first)) {
+        if let Ok(Statement::Join(join)) = query::parse(input.trim()) {
+            let output = query::join(&session.db, |name| session.database(name), &join);
+            print_query_output(&output.map_err(|e| e.to_string())?, mode);
+            return Ok(Flow::Continue);
+        }
+    }
+
+    // "select a.5" works on the record with key 5 of the database attached as a
+    let db = match parts.get(1).and_then(|word| word.split_once('.')) {
+        Some((name, key))
+            if ["select", "delete", "insert", "update", "upsert", "history"].contains(&parts[0])
+                && key.parse::<i32>().is_ok() =>
+        {
+            let db = session
+                .database_mut(name)
+                .ok_or_else(|| format!("No database is open or attached as '{}'", name))?;
+            parts[1] = key;
+            db
+        }
+        _ => &mut session.db,
+    };
+
+    match parts.as_slice() {
+        ["exit"] => return Ok(Flow::Exit),
+        // Lines whose second word is not a key are SQL, e.g. "update users set ..."
+        [command @ ("insert" | "update" | "upsert"), key, _, ..] if key.parse::<i32>().is_ok() => {
+            let key = key.parse::<i32>().unwrap_or_default();
+
+            // A trailing "ttl <secs>" is not part of the value
+            let (values, ttl) = match &words[2..] {
+                [values @ .., word, ttl] if !values.is_empty() && word.is_bare("ttl") => {
+                    (values, Some(ttl.text.as_str()))
+                }
+                values => (values, None),
+            };
+            let ttl = match ttl.map(|ttl| ttl.parse::<u64>()).transpose() {
+                Ok(ttl) => ttl,
+                Err(_) => return Err("Invalid TTL".to_string()),
+            };
+
+            // A row takes a word per column, a plain value the rest of the line
+            let value = match db.schema() {
+                Some(schema) => {
+                    let cells: Vec<&str> = values.iter().map(|word| word.text.as_str()).collect();
+                    parse_row(schema, &cells).and_then(|row| schema.encode(&row))
+                }
+                None => Ok(words::value(input, values)),
+            };
+            let result = value
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                .and_then(|value| Ok((db.put(key, value.clone(), ttl, write_mode(command))?, value)));
+            match result {
+                Ok((previous, value)) => {
+                    let expires = ttl.map(|ttl| format!(" (expires in {}s)", ttl)).unwrap_or_default();
+                    println!(
+                        "{}: {} => {}{}",
+                        written(&previous),
+                        key,
+                        display_value(db.schema(), &value),
+                        expires
+                    )
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        ["select"] => {
+            let records = db.tree().get_all_records();
+            if mode != Mode::List {
+                let (columns, rows) = record_table(db.schema(), &records);
+                format::print_rows(mode, &columns, &rows);
+            } else if records.is_empty() {
+                println!("No records found");
+            } else {
+                println!("All records:");
+                for record in records {
+                    println!("- {} => {}", record.key, display_value(db.schema(), &record.value));
+                }
+            }
+        }
+        // Anything but a key, e.g. "select *" or "select count(*)", is a query
+        ["select", key] if key.parse::<i32>().is_ok() => {
+            if let Ok(key) = key.parse::<i32>() {
+                if mode != Mode::List {
+                    let record = db.tree().search(key).map(|value| Record { key, value });
+                    let records: Vec<Record> = record.into_iter().collect();
+                    let (columns, rows) = record_table(db.schema(), &records);
+                    format::print_rows(mode, &columns, &rows);
+                } else if let Some(value) = db.tree().search(key) {
+                    println!("Found: {} => {}", key, display_value(db.schema(), &value));
                 } else {
-                    eprintln!("Invalid key");
+                    println!("Key {} not found", key);
+                }
+            }
+        }
+        ["select", key, "as", "of", rest @ ..] => {
+            let as_of = match rest {
+                ["version", version] => version.parse::<u64>().ok().map(AsOf::Version),
+                [timestamp] => timestamp.parse::<u64>().ok().map(AsOf::Time),
+                _ => None,
+            };
+
+            match (key.parse::<i32>(), as_of) {
+                (Ok(_), _) if !db.tree().history_enabled() => {
+                    println!("History is off, use 'history on' to start keeping versions");
                 }
+                (Ok(key), Some(as_of)) => match db.tree().search_as_of(key, as_of) {
+                    Some(value) => println!("Found: {} => {}", key, display_value(db.schema(), &value)),
+                    None => println!("Key {} not found", key),
+                },
+                (Err(_), _) => return Err("Invalid key".to_string()),
+                (_, None) => return Err("Invalid timestamp or version".to_string()),
             }
-            ["select"] => {
-                let records = tree.get_all_records();
-                if records.is_empty() {
-                    println!("No records found");
+        }
+        ["history", "on"] => match db.set_history(true) {
+            Ok(_) => println!("History enabled"),
+            Err(e) => return Err(e.to_string()),
+        },
+        ["history", "off"] => match db.set_history(false) {
+            Ok(_) => println!("History disabled"),
+            Err(e) => return Err(e.to_string()),
+        },
+        ["history", key] => {
+            if let Ok(key) = key.parse::<i32>() {
+                let versions = db.tree().history(key);
+                if !db.tree().history_enabled() {
+                    println!("History is off, use 'history on' to start keeping versions");
+                } else if versions.is_empty() {
+                    println!("No history for key {}", key);
                 } else {
-                    println!("All records:");
-                    for record in records {
-                        println!("- {} => {}", record.key, record.value);
+                    println!("History of key {}:", key);
+                    for version in versions {
+                        match version.value {
+                            Some(value) => println!(
+                                "- v{} @ {} set {}",
+                                version.version,
+                                version.timestamp,
+                                display_value(db.schema(), &value)
+                            ),
+                            None => println!(
+                                "- v{} @ {} deleted",
+                                version.version, version.timestamp
+                            ),
+                        }
                     }
                 }
+            } else {
+                return Err("Invalid key".to_string());
             }
-            ["select", key] => {
-                if let Ok(key) = key.parse::<i32>() {
-                    if let Some(value) = tree.search(key) {
-                        println!("Found: {} => {}", key, value);
-                    } else {
-                        println!("Key {} not found", key);
-                    }
-                } else {
-                    eprintln!("Invalid key");
+        }
+        ["schema"] => match db.schema() {
+            Some(schema) => println!("Schema: key INT, {}", schema),
+            None => println!("No schema, each record holds a single text value"),
+        },
+        ["schema", "none"] => match db.set_schema(None) {
+            Ok(_) => println!("Schema removed"),
+            Err(e) => return Err(e.to_string()),
+        },
+        ["schema", ..] => {
+            let definition = input.trim()["schema".len()..].trim();
+            let result = definition
+                .parse::<Schema>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                .and_then(|schema| db.set_schema(Some(schema)));
+            match result {
+                Ok(_) => println!("Schema set: key INT, {}", definition),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        ["tables"] => {
+            let mut names = vec![MAIN_TABLE.to_string()];
+            names.extend(db.table_names());
+            for name in names {
+                if let Some(table) = db.table(&name) {
+                    println!("- {}", describe_table(&name, table));
                 }
             }
-            ["delete", key] => {
-                if let Ok(key) = key.parse::<i32>() {
-                    let deleted = tree.delete(key);
-                    if deleted {
-                        save_records(&file_path, &tree.get_all_records())?;
-                        println!("Deleted key {}", key);
-                    } else {
-                        println!("Key {} not found", key);
-                    }
-                } else {
-                    eprintln!("Invalid key");
+        }
+        ["delete", key] => {
+            if let Ok(key) = key.parse::<i32>() {
+                match db.delete(key) {
+                    Ok(true) => println!("Deleted key {}", key),
+                    Ok(false) => println!("Key {} not found", key),
+                    Err(e) => return Err(e.to_string()),
                 }
+            } else {
+                return Err("Invalid key".to_string());
             }
-            ["analyze", key] => {
-                if let Ok(key) = key.parse::<i32>() {
-                    analyze_performance(key)?;
-                } else {
-                    eprintln!("Invalid key");
+        }
+        ["analyze", key] => {
+            if let Ok(key) = key.parse::<i32>() {
+                analyze_performance(key).map_err(|e| e.to_string())?;
+            } else {
+                return Err("Invalid key".to_string());
+            }
+        }
+        ["search", terms @ ..] if !terms.is_empty() => {
+            let results = db.search(&terms.join(" "));
+            if results.is_empty() {
+                println!("No records found");
+            } else {
+                println!("Found {} records:", results.len());
+                for (record, score) in results {
+                    println!(
+                        "- {} => {} (score {:.3})",
+                        record.key,
+                        display_value(db.schema(), &record.value),
+                        score
+                    );
                 }
             }
-            ["exit"] => break,
-            _ => println!("Invalid command"),
         }
+        ["explain", ..] if explain_statement(&parts).is_some() => {
+            match query::run(db, &explain_statement(&parts).unwrap_or_default()) {
+                Ok(output) => print_query_output(&output, mode),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        [first, ..] if is_query(first) => match query::run(db, input.trim()) {
+            Ok(output) => print_query_output(&output, mode),
+            Err(e) => return Err(e.to_string()),
+        },
+        _ => println!("Invalid command"),
     }
+    Ok(Flow::Continue)
+}
 
-    Ok(())
+fn print_help() {
+    println!("Commands:");
+    println!("  insert <key> <value>            - Insert a new record, failing if the key exists");
+    println!("  update <key> <value>            - Replace a record, failing if the key does not exist");
+    println!("  upsert <key> <value>            - Insert a record or replace the existing one");
+    println!("  insert <key> <value> ttl <secs> - Insert a record that expires after <secs> seconds");
+    println!("  insert <key> <v1> <v2> ...      - Insert a row when the database has a schema");
+    println!("  select                          - List all records");
+    println!("  select <key>                    - Find specific record");
+    println!("  select <key> as of <timestamp>  - Read a record at a past time");
+    println!("  select <key> as of version <n>  - Read a record at a past version");
+    println!("  delete <key>                    - Delete a record");
+    println!("  search <terms>                  - Find records containing words starting with the terms");
+    println!("  history on|off                  - Start or stop keeping previous versions");
+    println!("  history <key>                   - List the changes made to a key");
+    println!("  schema                          - Show the columns of each row");
+    println!("  schema <col> <TYPE>, ...        - Set the schema, e.g. schema name TEXT(32), age INT NULL");
+    println!("  schema none                     - Remove the schema");
+    println!("  tables                          - List the tables in the database");
+    println!("  SELECT/INSERT/UPDATE/DELETE ... - Run a query, e.g. SELECT * WHERE key BETWEEN 1 AND 9");
+    println!("  SELECT COUNT(*), MAX(key) ...   - Aggregate records, e.g. SELECT AVG(age) WHERE key BETWEEN 1 AND 9");
+    println!("  SELECT * FROM a [LEFT] JOIN b   - Pair the records of two tables by key");
+    println!("  CREATE TABLE/DROP TABLE ...     - Add or remove a table, e.g. CREATE TABLE users (name TEXT)");
+    println!("  CREATE INDEX/DROP INDEX ...     - Index a column, e.g. CREATE INDEX ON users (name)");
+    println!("  explain <command>               - Show how a lookup or query finds its records, e.g. explain select 5");
+    println!("  analyze <key>                   - Compare search performance across data structures");
+    println!("  copy <db>.<key> -> <db>.<key>   - Copy a record, e.g. copy 5 -> archive.5 after .attach");
+    println!("  undo                            - Revert the records changed by the last command");
+    println!("  redo                            - Make the changes of the last undone command again");
+    for (_, synopsis, description) in meta::COMMANDS {
+        println!("  {:<31} - {}", synopsis, description);
+    }
+}
+
+fn write_mode(command: &str) -> WriteMode {
+    match command {
+        "insert" => WriteMode::Insert,
+        "update" => WriteMode::Update,
+        _ => WriteMode::Upsert,
+    }
+}
+
+// "Inserted" or "Updated", depending on whether a value was replaced
+fn written(previous: &Option<String>) -> &'static str {
+    if previous.is_some() {
+        "Updated"
+    } else {
+        "Inserted"
+    }
+}
+
+// Copy the value of a record to another key, of the same database or another
+// one, replacing any value there
+fn copy(session: &mut Session, from: &str, to: &str) -> Result<Flow, String> {
+    let (from_db, from_key) = session.record(from)?;
+    let (to_db, to_key) = session.record(to)?;
+    let value = session
+        .database(&from_db)
+        .and_then(|db| db.tree().search(from_key))
+        .ok_or_else(|| format!("Key {} not found in '{}'", from_key, from_db))?;
+
+    let db = session.database_mut(&to_db).ok_or_else(|| format!("No database '{}'", to_db))?;
+    let previous = db.put(to_key, value, None, WriteMode::Upsert).map_err(|e| e.to_string())?;
+    let action = if previous.is_some() { "replacing its value" } else { "a new record" };
+    println!("Copied {}.{} to {}.{}, {}", from_db, from_key, to_db, to_key, action);
+    Ok(Flow::Continue)
+}
+
+// The lookup commands explain as the query they amount to, e.g. "explain
+// select 5" as EXPLAIN SELECT * WHERE key = 5
+fn explain_statement(parts: &[&str]) -> Option<String> {
+    match parts {
+        ["explain", "select"] => Some("EXPLAIN SELECT *".to_string()),
+        ["explain", "select", key] if key.parse::<i32>().is_ok() => {
+            Some(format!("EXPLAIN SELECT * WHERE key = {}", key))
+        }
+        ["explain", "delete", key] if key.parse::<i32>().is_ok() => {
+            Some(format!("EXPLAIN DELETE WHERE key = {}", key))
+        }
+        _ => None,
+    }
+}
+
+// Lines that don't match a command above but start like a query are run as one
+fn is_query(word: &str) -> bool {
+    ["select", "insert", "upsert", "update", "delete", "create", "drop", "explain"]
+        .iter()
+        .any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+// Parse the cells of a row typed after "insert <key>"
+fn parse_row(schema: &Schema, cells: &[&str]) -> Result<Vec<Value>, String> {
+    if cells.len() != schema.columns.len() {
+        return Err(format!(
+            "Expected {} values ({}) but got {}",
+            schema.columns.len(),
+            schema.names().join(", "),
+            cells.len()
+        ));
+    }
+    cells
+        .iter()
+        .enumerate()
+        .map(|(index, cell)| schema.parse_cell(index, cell))
+        .collect()
+}
+
+// e.g. "users (3 records, name TEXT, age INT NULL, indexed on age)"
+fn describe_table(name: &str, table: &Database) -> String {
+    let mut details = vec![format!("{} records", table.tree().get_all_records().len())];
+    if let Some(schema) = table.schema() {
+        details.push(schema.to_string());
+    }
+    if !table.indexes().is_empty() {
+        let columns: Vec<&str> = table.indexes().iter().map(|i| i.column()).collect();
+        details.push(format!("indexed on {}", columns.join(", ")));
+    }
+    format!("{} ({})", name, details.join(", "))
+}
+
+// Show a row as its cells separated by " | ", or a plain value as it is
+fn display_value(schema: Option<&Schema>, value: &str) -> String {
+    match schema.map(|schema| schema.decode(value)) {
+        Some(Ok(cells)) => {
+            let cells: Vec<String> = cells.iter().map(|cell| cell.to_string()).collect();
+            cells.join(" | ")
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn print_query_output(output: &QueryOutput, mode: Mode) {
+    match output {
+        QueryOutput::Rows { columns, rows } => format::print_rows(mode, columns, rows),
+        QueryOutput::Explain(explain) => print_explain(explain),
+        QueryOutput::Changed { .. } | QueryOutput::Done(_) => println!("{}", output.summary()),
+    }
+}
+
+// The columns and cells of records: the key and the value, or the key and each
+// column of a row
+fn record_table(schema: Option<&Schema>, records: &[Record]) -> (Vec<String>, Vec<Vec<Value>>) {
+    let mut header = vec!["key".to_string()];
+    match schema {
+        Some(schema) => header.extend(schema.names().iter().map(|name| name.to_string())),
+        None => header.push("value".to_string()),
+    }
+    let rows = records
+        .iter()
+        .map(|record| {
+            let mut cells = vec![Value::Int(record.key.into())];
+            match schema.map(|schema| schema.decode(&record.value)) {
+                Some(Ok(row)) => cells.extend(row),
+                _ => cells.push(Value::Text(record.value.clone())),
+            }
+            cells
+        })
+        .collect();
+    (header, rows)
+}
+
+// Run CLI commands one per line, a query going on over the next lines until
+// its quotes and parentheses are closed. Blank lines and lines starting with
+// # or -- are skipped. The script stops at the first command that fails, whose
+// line is given in the error.
+fn run_script(reader: impl BufRead, mut run: impl FnMut(&str) -> Result<Flow, String>) -> Result<Flow, String> {
+    let mut command = String::new();
+    let mut start = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read the script: {}", e))?;
+        if command.is_empty() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("--") {
+                continue;
+            }
+            start = number + 1;
+        } else {
+            command.push('\n');
+        }
+        command.push_str(&line);
+        if !editor::is_complete(&command) {
+            continue;
+        }
+
+        match run(&command) {
+            Ok(Flow::Continue) => command.clear(),
+            Ok(Flow::Exit) => return Ok(Flow::Exit),
+            Err(e) => return Err(format!("Line {}: {}", start, e)),
+        }
+    }
+
+    if !command.is_empty() {
+        return Err(format!("Line {}: the command is not finished at the end of the script", start));
+    }
+    Ok(Flow::Continue)
+}
+
+fn print_explain(explain: &Explain) {
+    println!("Access path:   {}", explain.access_path);
+    print_trace("B-Tree", &explain.tree);
+    if let Some(index) = &explain.index {
+        print_trace("Index", index);
+    }
+    println!("Records:       {}", explain.records);
+    println!("Rows:          {} estimated, {} actual", explain.estimated_rows, explain.actual_rows);
+}
+
+fn print_trace(name: &str, trace: &Trace) {
+    println!("{} nodes visited: {}", name, trace.nodes());
+    for (level, nodes) in trace.nodes_per_level.iter().enumerate() {
+        println!("  level {}: {}", level, nodes);
+    }
+    println!("{} comparisons: {}", name, trace.comparisons);
 }
 
 fn analyze_performance(key: i32) -> io::Result<()> {
@@ -152,12 +772,12 @@ fn analyze_performance(key: i32) -> io::Result<()> {
     
     // Comparison analysis
     println!("\n----- Analysis -----");
-    let mut times = vec![
+    let mut times = [
         ("B-Tree", btree_result.1),
         ("HashMap", hashtable_result.1),
         ("Array", array_result.1),
     ];
-    times.sort_by(|a, b| a.1.cmp(&b.1));
+    times.sort_by_key(|t| t.1);
     
     println!("Fastest: {} ({:?})", times[0].0, times[0].1);
     println!("Slowest: {} ({:?})", times[2].0, times[2].1);
@@ -198,4 +818,26 @@ fn result_to_string(result: Option<String>) -> String {
         Some(value) => format!("Found \"{}\"", value),
         None => "Not found".to_string(),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn lowercase_select_of_anything_but_a_key_is_a_query() {
+        let dir = std::env::temp_dir().join(format!("ryou-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config::parse_from(["database", "--data-dir", &dir.to_string_lossy()]);
+        let mut session = Session::new(&config, "db").unwrap();
+
+        run_line(&mut session, "insert 5 five").unwrap();
+        for line in ["select *", "select value", "select count(*)", "select 5", "select 6"] {
+            assert!(run_line(&mut session, line).is_ok(), "{}", line);
+        }
+        assert!(run_line(&mut session, "select five").is_err_and(|e| e != "Invalid key"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value as Json};
+use ureq::Agent;
+
+use crate::config::Config;
+use crate::query::Value;
+use crate::schema::{value_from_json, Schema};
+
+use super::{analyze_performance, explain_statement, format, is_query, meta, parse_row, print_help, run_script, words};
+use super::{Flow, Mode};
+
+// How long a request may take before the command fails
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// A CLI session on a database held by a running web server. Each command is
+// sent to the server's /api/db/... endpoints, so the file is only ever written
+// by the server.
+pub struct Remote {
+    agent: Agent,
+    // e.g. http://127.0.0.1:8080/api
+    api: String,
+    db_name: String,
+    read_only: bool,
+    timer: bool,
+    mode: Mode,
+}
+
+impl Remote {
+    pub fn connect(url: &str, db_name: &str, config: &Config) -> io::Result<Remote> {
+        let agent: Agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(TIMEOUT))
+            .build()
+            .into();
+        let mut remote = Remote {
+            agent,
+            api: format!("{}/api", url.trim_end_matches('/')),
+            db_name: String::new(),
+            read_only: config.read_only,
+            timer: false,
+            mode: config.format.unwrap_or(Mode::List),
+        };
+        remote.open(db_name).map_err(io::Error::other)?;
+        Ok(remote)
+    }
+
+    // e.g. "mydb on http://127.0.0.1:8080/api"
+    pub fn describe(&self) -> String {
+        format!("{} on {}", self.db_name, self.api)
+    }
+
+    // Connect the server to a database and send the following commands to it
+    fn open(&mut self, db_name: &str) -> Result<(), String> {
+        let body = json!({ "db_name": db_name, "read_only": self.read_only });
+        let response = self.send(self.agent.post(format!("{}/connect", self.api)).send_json(body))?;
+        println!("{}", message(&response));
+        self.db_name = db_name.to_string();
+        Ok(())
+    }
+
+    pub fn run_line(&mut self, input: &str) -> Result<Flow, String> {
+        let words = words::split(input)?;
+        let start = Instant::now();
+        let result = self.run_command(input, &words);
+        if self.timer {
+            println!("Run time: {:.6}s", start.elapsed().as_secs_f64());
+        }
+        result
+    }
+
+    fn run_command(&mut self, input: &str, words: &[words::Word]) -> Result<Flow, String> {
+        let parts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+
+        match parts.as_slice() {
+            [".exit"] | ["exit"] => return Ok(Flow::Exit),
+            [".help"] => print_help(),
+            [".open", db_name] => {
+                self.open(db_name)?;
+                println!("Using database: {}", self.describe());
+            }
+            [".timer", setting @ ("on" | "off")] => self.timer = *setting == "on",
+            [".mode", name @ ..] if name.len() <= 1 => meta::set_mode(&mut self.mode, name.first().copied())?,
+            [".read", path] => {
+                let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+                let reader = BufReader::new(file);
+                return run_script(reader, |line| self.run_line(line)).map_err(|e| format!("{}: {}", path, e));
+            }
+            // These work on the database file, which only the server opens
+            [command @ (".attach" | ".detach" | ".databases" | ".dump" | ".restore" | ".import" | ".export" | ".stats"
+            | ".btree"), ..] => {
+                return Err(format!("{} is not available with --remote", command));
+            }
+            [command, ..] if command.starts_with('.') => return meta::usage(command),
+            [command @ ("insert" | "update" | "upsert"), key, _, ..] if key.parse::<i32>().is_ok() => {
+                let key = key.parse::<i32>().unwrap_or_default();
+
+                // A trailing "ttl <secs>" is not part of the value
+                let (values, ttl) = match &words[2..] {
+                    [values @ .., word, ttl] if !values.is_empty() && word.is_bare("ttl") => {
+                        (values, Some(ttl.text.as_str()))
+                    }
+                    values => (values, None),
+                };
+                let ttl = match ttl.map(|ttl| ttl.parse::<u64>()).transpose() {
+                    Ok(ttl) => ttl,
+                    Err(_) => return Err("Invalid TTL".to_string()),
+                };
+
+                // A row is sent as its columns, a plain value as the rest of the line
+                let mut body = Map::new();
+                match self.schema()? {
+                    Some(schema) => {
+                        let cells: Vec<&str> = values.iter().map(|word| word.text.as_str()).collect();
+                        let row = parse_row(&schema, &cells)?;
+                        for (name, cell) in schema.names().into_iter().zip(row) {
+                            body.insert(name, serde_json::to_value(cell).unwrap_or_default());
+                        }
+                    }
+                    None => {
+                        body.insert("value".to_string(), Json::from(words::value(input, values)));
+                    }
+                }
+                if let Some(ttl) = ttl {
+                    body.insert("ttl".to_string(), Json::from(ttl));
+                }
+
+                let url = format!("{}/records", self.db_url());
+                let request = match *command {
+                    "insert" => {
+                        body.insert("key".to_string(), Json::from(key));
+                        self.agent.post(url).send_json(body)
+                    }
+                    "update" => self.agent.patch(format!("{}/{}", url, key)).send_json(body),
+                    _ => self.agent.put(format!("{}/{}", url, key)).send_json(body),
+                };
+                println!("{}", message(&self.send(request)?));
+            }
+            ["select"] => {
+                let response = self.send(self.agent.get(format!("{}/records", self.db_url())).call())?;
+                let (columns, rows) = records(&response);
+                if self.mode != Mode::List {
+                    format::print_rows(self.mode, &columns, &rows);
+                } else if rows.is_empty() {
+                    println!("No records found");
+                } else {
+                    println!("All records:");
+                    for row in rows {
+                        println!("- {}", describe_row(&row));
+                    }
+                }
+            }
+            // Anything but a key, e.g. "select *" or "select count(*)", is a query
+            ["select", key] if key.parse::<i32>().is_ok() => {
+                let key = key.parse::<i32>().map_err(|_| "Invalid key".to_string())?;
+                let request = self.agent.get(format!("{}/records/{}", self.db_url(), key)).call();
+                self.print_record(request)?;
+            }
+            ["select", key, "as", "of", rest @ ..] => {
+                let key = key.parse::<i32>().map_err(|_| "Invalid key".to_string())?;
+                let request = self.agent.get(format!("{}/records/{}", self.db_url(), key));
+                let request = match rest {
+                    ["version", version] if version.parse::<u64>().is_ok() => request.query("version", *version),
+                    [timestamp] if timestamp.parse::<u64>().is_ok() => request.query("as_of", *timestamp),
+                    _ => return Err("Invalid timestamp or version".to_string()),
+                };
+                self.print_record(request.call())?;
+            }
+            ["delete", key] => {
+                let key = key.parse::<i32>().map_err(|_| "Invalid key".to_string())?;
+                let request = self.agent.delete(format!("{}/records/{}", self.db_url(), key)).call();
+                println!("{}", message(&self.send(request)?));
+            }
+            ["history", key] if key.parse::<i32>().is_ok() => {
+                let request = self.agent.get(format!("{}/records/{}/history", self.db_url(), key)).call();
+                let response = self.send(request)?;
+                let versions = response["data"].as_array().cloned().unwrap_or_default();
+                if versions.is_empty() {
+                    println!("No history for key {}", key);
+                } else {
+                    println!("History of key {}:", key);
+                    for version in versions {
+                        match version["value"].as_str() {
+                            Some(value) => {
+                                println!("- v{} @ {} set {}", version["version"], version["timestamp"], value)
+                            }
+                            None => println!("- v{} @ {} deleted", version["version"], version["timestamp"]),
+                        }
+                    }
+                }
+            }
+            ["schema"] => match self.schema()? {
+                Some(schema) => println!("Schema: key INT, {}", schema),
+                None => println!("No schema, each record holds a single text value"),
+            },
+            ["schema", rest @ ..] => {
+                let definition = match rest {
+                    ["none"] => Json::Null,
+                    _ => Json::from(input.trim()["schema".len()..].trim()),
+                };
+                let request = self
+                    .agent
+                    .put(format!("{}/schema", self.db_url()))
+                    .send_json(json!({ "schema": definition }));
+                println!("{}", message(&self.send(request)?));
+            }
+            ["tables"] => {
+                let response = self.send(self.agent.get(format!("{}/tables", self.db_url())).call())?;
+                for table in response["data"].as_array().into_iter().flatten() {
+                    let mut details = vec![format!("{} records", table["records"])];
+                    if let Some(schema) = table["schema"].as_str() {
+                        details.push(schema.to_string());
+                    }
+                    let indexes: Vec<&str> =
+                        table["indexes"].as_array().into_iter().flatten().filter_map(Json::as_str).collect();
+                    if !indexes.is_empty() {
+                        details.push(format!("indexed on {}", indexes.join(", ")));
+                    }
+                    println!("- {} ({})", table["name"].as_str().unwrap_or_default(), details.join(", "));
+                }
+            }
+            ["search", terms @ ..] if !terms.is_empty() => {
+                let request = self.agent.get(format!("{}/search", self.db_url())).query("q", terms.join(" "));
+                let response = self.send(request.call())?;
+                let hits = response["data"].as_array().cloned().unwrap_or_default();
+                if hits.is_empty() {
+                    println!("No records found");
+                } else {
+                    println!("Found {} records:", hits.len());
+                    for mut hit in hits {
+                        let score = hit["score"].as_f64().unwrap_or_default();
+                        if let Some(fields) = hit.as_object_mut() {
+                            fields.shift_remove("score");
+                        }
+                        let (_, rows) = records(&json!({ "data": [hit] }));
+                        for row in rows {
+                            println!("- {} (score {:.3})", describe_row(&row), score);
+                        }
+                    }
+                }
+            }
+            ["history", "on" | "off"] => {
+                return Err("History is turned on when connecting, not with --remote".to_string());
+            }
+            [command @ ("undo" | "redo")] => return Err(format!("{} is not available with --remote", command)),
+            ["analyze", key] => {
+                let key = key.parse::<i32>().map_err(|_| "Invalid key".to_string())?;
+                analyze_performance(key).map_err(|e| e.to_string())?;
+            }
+            ["explain", ..] if explain_statement(&parts).is_some() => {
+                self.query(&explain_statement(&parts).unwrap_or_default())?;
+            }
+            [first, ..] if is_query(first) => self.query(input)?,
+            _ => println!("Invalid command"),
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn db_url(&self) -> String {
+        format!("{}/db/{}", self.api, self.db_name)
+    }
+
+    // The body of a successful response, or the message of a failed one
+    fn send(&self, response: Result<ureq::http::Response<ureq::Body>, ureq::Error>) -> Result<Json, String> {
+        let mut response = response.map_err(|e| format!("Failed to reach {}: {}", self.api, e))?;
+        let body: Json = response
+            .body_mut()
+            .read_json()
+            .map_err(|e| format!("Unexpected response from {}: {}", self.api, e))?;
+        if body["success"].as_bool() == Some(true) {
+            Ok(body)
+        } else {
+            Err(message(&body).to_string())
+        }
+    }
+
+    // The schema of the database, read from the server since it can change
+    fn schema(&self) -> Result<Option<Schema>, String> {
+        let response = self.send(self.agent.get(format!("{}/schema", self.db_url())).call())?;
+        let columns = match response["columns"].as_array() {
+            Some(columns) if !columns.is_empty() => columns,
+            _ => return Ok(None),
+        };
+        let definitions: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                let null = if column["nullable"].as_bool() == Some(true) { " NULL" } else { "" };
+                let name = column["name"].as_str().unwrap_or_default();
+                format!("{} {}{}", name, column["type"].as_str().unwrap_or_default(), null)
+            })
+            .collect();
+        definitions.join(", ").parse::<Schema>().map(Some)
+    }
+
+    fn print_record(&self, response: Result<ureq::http::Response<ureq::Body>, ureq::Error>) -> Result<(), String> {
+        let response = self.send(response)?;
+        let (columns, rows) = records(&response);
+        if self.mode == Mode::List {
+            for row in &rows {
+                println!("Found: {}", describe_row(row));
+            }
+        } else {
+            format::print_rows(self.mode, &columns, &rows);
+        }
+        Ok(())
+    }
+
+    fn query(&self, input: &str) -> Result<(), String> {
+        let request = self
+            .agent
+            .post(format!("{}/query", self.db_url()))
+            .send_json(json!({ "query": input.trim() }));
+        let response = self.send(request)?;
+        if let Some(explain) = response.get("explain") {
+            println!("{}", serde_json::to_string_pretty(explain).unwrap_or_default());
+            return Ok(());
+        }
+        let columns: Vec<String> = match response["columns"].as_array() {
+            Some(columns) => columns.iter().filter_map(Json::as_str).map(str::to_string).collect(),
+            None => {
+                println!("{}", message(&response));
+                return Ok(());
+            }
+        };
+        let rows: Vec<Vec<Value>> = response["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|row| columns.iter().map(|column| cell(&row[column])).collect())
+            .collect();
+        format::print_rows(self.mode, &columns, &rows);
+        Ok(())
+    }
+}
+
+fn message(response: &Json) -> &str {
+    response["message"].as_str().unwrap_or_default()
+}
+
+fn cell(json: &Json) -> Value {
+    value_from_json(json).unwrap_or_else(|_| Value::Text(json.to_string()))
+}
+
+// The columns and cells of the records in a response: the key, then the value
+// or the columns of a row
+fn records(response: &Json) -> (Vec<String>, Vec<Vec<Value>>) {
+    let records: Vec<&Map<String, Json>> =
+        response["data"].as_array().into_iter().flatten().filter_map(Json::as_object).collect();
+    let columns: Vec<String> = match records.first() {
+        Some(record) => record.keys().cloned().collect(),
+        None => vec!["key".to_string(), "value".to_string()],
+    };
+    let rows = records
+        .iter()
+        .map(|record| columns.iter().map(|column| cell(record.get(column).unwrap_or(&Json::Null))).collect())
+        .collect();
+    (columns, rows)
+}
+
+// e.g. "5 => ann | 30", as the CLI shows a local record
+fn describe_row(row: &[Value]) -> String {
+    let cells: Vec<String> = row.iter().skip(1).map(|cell| cell.to_string()).collect();
+    match row.first() {
+        Some(key) => format!("{} => {}", key, cells.join(" | ")),
+        None => String::new(),
+    }
+}
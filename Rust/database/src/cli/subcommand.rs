@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::btree::Record;
+use crate::config::{Command, Config};
+use crate::db::{Database, WriteMode};
+use crate::query;
+
+use super::{display_value, dump, format, parse_row, print_query_output, record_table, written};
+use super::{run_line, run_script, Mode, Session};
+
+// Exit codes of the subcommands
+pub const EXIT_OK: i32 = 0;
+// A command failed, e.g. a value did not fit the schema
+pub const EXIT_FAILURE: i32 = 1;
+// Wrong arguments exit with 2, from the argument parser
+// The key or the database does not exist
+pub const EXIT_NOT_FOUND: i32 = 3;
+
+// Run a one-shot command and return the exit code for the process
+pub fn run(command: Command, config: &Config) -> i32 {
+    let db_name = match &command {
+        Command::Get { db, .. }
+        | Command::Put { db, .. }
+        | Command::Delete { db, .. }
+        | Command::Dump { db }
+        | Command::Query { db, .. }
+        | Command::Run { db, .. } => db.clone(),
+    };
+    // Read the script before opening the database, which creates it
+    let script = match &command {
+        Command::Run { file: Some(path), .. } => match File::open(path) {
+            Ok(file) => Some(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                return EXIT_FAILURE;
+            }
+        },
+        _ => None,
+    };
+
+    // Reading a database that does not exist should not create it
+    let reads_only = matches!(command, Command::Get { .. } | Command::Delete { .. } | Command::Dump { .. });
+    if reads_only && !Path::new(&config.database_path(&db_name)).exists() {
+        eprintln!("Database '{}' not found", db_name);
+        return EXIT_NOT_FOUND;
+    }
+    let mut session = match Session::new(config, &db_name) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Failed to open database '{}': {}", db_name, e);
+            return EXIT_FAILURE;
+        }
+    };
+    let db = &mut session.db;
+
+    match command {
+        Command::Get { key, .. } => get(db, key, config.format),
+        Command::Put { key, value, .. } => put(db, key, &value),
+        Command::Delete { key, .. } => delete(db, key),
+        // Without --format the commands that rebuild the database are printed
+        Command::Dump { .. } => {
+            match config.format {
+                Some(mode) => {
+                    let (columns, rows) = record_table(db.schema(), &db.tree().get_all_records());
+                    format::print_rows(mode, &columns, &rows);
+                }
+                None => print!("{}", dump::script(db, &db_name)),
+            }
+            EXIT_OK
+        }
+        Command::Query { query, .. } => match query::run(db, &query.join(" ")) {
+            Ok(output) => {
+                print_query_output(&output, config.format.unwrap_or(Mode::List));
+                EXIT_OK
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_FAILURE
+            }
+        },
+        Command::Run { .. } => {
+            let result = match script {
+                Some(script) => run_script(script, |line| run_line(&mut session, line)),
+                None => run_script(io::stdin().lock(), |line| run_line(&mut session, line)),
+            };
+            match result {
+                Ok(_) => EXIT_OK,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    EXIT_FAILURE
+                }
+            }
+        }
+    }
+}
+
+// Print the value of a key, or its record in the given format
+fn get(db: &Database, key: i32, mode: Option<Mode>) -> i32 {
+    match db.tree().search(key) {
+        Some(value) => {
+            match mode {
+                Some(mode) => {
+                    let (columns, rows) = record_table(db.schema(), &[Record { key, value }]);
+                    format::print_rows(mode, &columns, &rows);
+                }
+                None => println!("{}", display_value(db.schema(), &value)),
+            }
+            EXIT_OK
+        }
+        None => {
+            eprintln!("Key {} not found", key);
+            EXIT_NOT_FOUND
+        }
+    }
+}
+
+// Insert or replace a record. With a schema each argument is a column,
+// otherwise the arguments are the value, separated by spaces.
+fn put(db: &mut Database, key: i32, value: &[String]) -> i32 {
+    let value = match db.schema() {
+        Some(schema) => {
+            let cells: Vec<&str> = value.iter().map(String::as_str).collect();
+            parse_row(schema, &cells).and_then(|row| schema.encode(&row))
+        }
+        None => Ok(value.join(" ")),
+    };
+
+    let result = value
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        .and_then(|value| Ok((db.put(key, value.clone(), None, WriteMode::Upsert)?, value)));
+    match result {
+        Ok((previous, value)) => {
+            println!("{}: {} => {}", written(&previous), key, display_value(db.schema(), &value));
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn delete(db: &mut Database, key: i32) -> i32 {
+    match db.delete(key) {
+        Ok(true) => {
+            println!("Deleted key {}", key);
+            EXIT_OK
+        }
+        Ok(false) => {
+            eprintln!("Key {} not found", key);
+            EXIT_NOT_FOUND
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_FAILURE
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::db::{Change, Database, Write, WriteMode};
+
+use super::Session;
+
+// Number of commands that can be undone, the oldest being forgotten first
+const MAX_STEPS: usize = 100;
+
+// The commands of a session that changed records, to undo and redo them
+#[derive(Default)]
+pub struct UndoStack {
+    done: Vec<Step>,
+    undone: Vec<Step>,
+    // The changes made by the running command, with the file of the database
+    // each was made in
+    journal: Arc<Mutex<Vec<(String, Change)>>>,
+}
+
+// The records one command changed, in every database and table it wrote to
+struct Step {
+    command: String,
+    edits: Vec<Edit>,
+}
+
+// Changes to one table of a database, known by its file so that it is found
+// again whatever name or alias it has by then
+struct Edit {
+    file_path: String,
+    table: String,
+    changes: Vec<Change>,
+}
+
+impl UndoStack {
+    // Journal every change made through `db` and its tables
+    pub fn watch(&self, db: &mut Database) {
+        let journal = self.journal.clone();
+        let file_path = db.file_path().to_string();
+        db.on_change(Box::new(move |change| {
+            journal.lock().unwrap().push((file_path.clone(), change.clone()))
+        }));
+    }
+
+    // Number of changes journaled so far
+    pub fn journaled(&self) -> usize {
+        self.journal.lock().unwrap().len()
+    }
+
+    // Drop the changes journaled after the first `len`, e.g. those read from
+    // files that other programs changed
+    pub fn forget(&self, len: usize) {
+        self.journal.lock().unwrap().truncate(len);
+    }
+}
+
+// Remember the records changed since the journal was last emptied as the step
+// of `command`. A new change makes the undone commands impossible to redo.
+pub fn record(session: &mut Session, command: &str) {
+    let stack = &mut session.undo;
+    let journal = std::mem::take(&mut *stack.journal.lock().unwrap());
+
+    let mut edits: Vec<Edit> = Vec::new();
+    for (file_path, change) in journal {
+        match edits.last_mut() {
+            Some(edit) if edit.file_path == file_path && edit.table == change.table => {
+                edit.changes.push(change)
+            }
+            _ => edits.push(Edit {
+                file_path,
+                table: change.table.clone(),
+                changes: vec![change],
+            }),
+        }
+    }
+    if edits.is_empty() {
+        return;
+    }
+
+    stack.undone.clear();
+    stack.done.push(Step {
+        command: command.trim().to_string(),
+        edits,
+    });
+    if stack.done.len() > MAX_STEPS {
+        stack.done.remove(0);
+    }
+}
+
+// Put back the values the last command replaced and remove the records it
+// added
+pub fn undo(session: &mut Session) -> Result<(), String> {
+    let Some(step) = session.undo.done.pop() else {
+        println!("Nothing to undo");
+        return Ok(());
+    };
+    match apply(session, &step, true) {
+        Ok(count) => {
+            println!("Undid '{}' ({} records)", step.command, count);
+            session.undo.undone.push(step);
+            Ok(())
+        }
+        Err((0, e)) => {
+            let message = format!("Cannot undo '{}': {}", step.command, e);
+            session.undo.done.push(step);
+            Err(message)
+        }
+        // The step is dropped, as it can no longer be undone or redone whole
+        Err((count, e)) => Err(format!("Undid '{}' only partly ({} records): {}", step.command, count, e)),
+    }
+}
+
+// Make the last undone command's changes again
+pub fn redo(session: &mut Session) -> Result<(), String> {
+    let Some(step) = session.undo.undone.pop() else {
+        println!("Nothing to redo");
+        return Ok(());
+    };
+    match apply(session, &step, false) {
+        Ok(count) => {
+            println!("Redid '{}' ({} records)", step.command, count);
+            session.undo.done.push(step);
+            Ok(())
+        }
+        Err((0, e)) => {
+            let message = format!("Cannot redo '{}': {}", step.command, e);
+            session.undo.undone.push(step);
+            Err(message)
+        }
+        Err((count, e)) => Err(format!("Redid '{}' only partly ({} records): {}", step.command, count, e)),
+    }
+}
+
+// Write the records of a step back as they were before it, or as it left them,
+// with their expiry, and save them. Nothing is written if a record was changed
+// since by something else. Each table is saved on its own, so if one fails the
+// tables before it stay written. Returns how many records were written, or
+// that many along with the error that stopped it.
+fn apply(session: &mut Session, step: &Step, backwards: bool) -> Result<usize, (usize, String)> {
+    let edits: Vec<&Edit> = if backwards {
+        step.edits.iter().rev().collect()
+    } else {
+        step.edits.iter().collect()
+    };
+
+    let mut batches = Vec::new();
+    for edit in edits {
+        // Each change as the value it finds and the value it writes, with the
+        // expiry of that value
+        let transitions: Vec<_> = if backwards {
+            edit.changes.iter().rev().map(|c| (c.key, &c.value, &c.previous, c.previous_expires_at)).collect()
+        } else {
+            edit.changes.iter().map(|c| (c.key, &c.previous, &c.value, c.expires_at)).collect()
+        };
+
+        let db = database(session, &edit.file_path).ok_or_else(|| {
+            (0, format!("database {} is no longer open, .open or .attach it first", edit.file_path))
+        })?;
+        let db = db
+            .table(&edit.table)
+            .ok_or_else(|| (0, format!("table '{}' no longer exists", edit.table)))?;
+
+        let mut keys = HashSet::new();
+        let mut writes = Vec::new();
+        for (key, from, to, expires_at) in transitions {
+            if keys.insert(key) && db.tree().search(key) != *from {
+                return Err((0, format!("key {} was changed since", key)));
+            }
+            writes.push(match to {
+                Some(value) => Write::Put {
+                    key,
+                    value: value.clone(),
+                    mode: WriteMode::Upsert,
+                    expires_at,
+                },
+                None => Write::Delete { key },
+            });
+        }
+        batches.push((edit, keys.len(), writes));
+    }
+
+    let mut count = 0;
+    for (edit, keys, writes) in batches {
+        let db = database_mut(session, &edit.file_path).and_then(|db| db.table_mut(&edit.table));
+        if let Some(db) = db {
+            db.write_all(writes).map_err(|e| (count, e.to_string()))?;
+            count += keys;
+        }
+    }
+    Ok(count)
+}
+
+// The open or an attached database stored in `file_path`
+fn database<'s>(session: &'s Session, file_path: &str) -> Option<&'s Database> {
+    std::iter::once(&session.db)
+        .chain(session.attached.values().map(|attached| &attached.db))
+        .find(|db| db.file_path() == file_path)
+}
+
+fn database_mut<'s>(session: &'s mut Session, file_path: &str) -> Option<&'s mut Database> {
+    std::iter::once(&mut session.db)
+        .chain(session.attached.values_mut().map(|attached| &mut attached.db))
+        .find(|db| db.file_path() == file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::cli::{run_line, Session};
+    use crate::config::Config;
+
+    fn config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("ryou-undo-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Config::parse_from(["database", "--data-dir", &dir.to_string_lossy()])
+    }
+
+    fn run(session: &mut Session, lines: &[&str]) {
+        for line in lines {
+            run_line(session, line).unwrap();
+        }
+    }
+
+    #[test]
+    fn undoes_commands_of_any_size() {
+        let config = config("size");
+        let mut session = Session::new(&config, "db").unwrap();
+        let values: Vec<String> = (1..=1500).map(|key| format!("({}, 'v{}')", key, key)).collect();
+        let insert = format!("INSERT VALUES {}", values.join(", "));
+        run(&mut session, &[&insert, "UPDATE SET value = 'x'", "undo"]);
+
+        assert_eq!(session.db.tree().len(), 1500);
+        assert_eq!(session.db.tree().search(1234).as_deref(), Some("v1234"));
+        run(&mut session, &["undo"]);
+        assert_eq!(session.db.tree().len(), 0);
+        std::fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[test]
+    fn records_come_back_with_their_ttl() {
+        let config = config("ttl");
+        let mut session = Session::new(&config, "db").unwrap();
+        run(&mut session, &["insert 1 one ttl 600", "delete 1", "undo"]);
+
+        assert_eq!(session.db.tree().search(1).as_deref(), Some("one"));
+        assert!(session.db.tree().expires_at(1).is_some());
+        std::fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+
+    #[test]
+    fn undoes_in_the_database_it_changed_after_reopening() {
+        let config = config("reopen");
+        let mut session = Session::new(&config, "db").unwrap();
+        run(&mut session, &["insert 1 one", ".attach other", "insert other.5 five", ".detach other"]);
+
+        assert!(run_line(&mut session, "undo").is_err());
+        run(&mut session, &[".open other", "undo", ".open db", "undo"]);
+        assert_eq!(session.db.tree().len(), 0);
+        std::fs::remove_dir_all(&config.data_dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+// A word of a command line, with where it was typed so that the rest of the
+// line can be taken as it is
+pub struct Word {
+    pub text: String,
+    // Whether any of it was quoted, so that e.g. "ttl" is a value and not an
+    // option
+    pub quoted: bool,
+    start: usize,
+    end: usize,
+}
+
+impl Word {
+    // Whether the word is `keyword` typed without quotes
+    pub fn is_bare(&self, keyword: &str) -> bool {
+        !self.quoted && self.text == keyword
+    }
+}
+
+// Split a command line into words at whitespace. A word starting with a single
+// or double quote runs to the matching quote, spaces included. A backslash
+// takes the next character as it is, except for \n and \t which are a newline
+// and a tab.
+pub fn split(input: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut text = String::new();
+        let mut quoted = false;
+        let mut end = start;
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            end = i + c.len_utf8();
+
+            match c {
+                // Quotes only open a string at the start of a word or after
+                // another string, so "don't" is typed as it is
+                '\'' | '"' if text.is_empty() || quoted => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some((i, ch)) if ch == c => {
+                                end = i + 1;
+                                break;
+                            }
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, escaped)) => text.push(unescape(escaped)),
+                                None => return Err("Unterminated escape at the end of the line".to_string()),
+                            },
+                            Some((_, ch)) => text.push(ch),
+                            None => return Err(format!("Unterminated {} quote", if c == '"' { "double" } else { "single" })),
+                        }
+                    }
+                }
+                '\\' => match chars.next() {
+                    Some((i, escaped)) => {
+                        text.push(unescape(escaped));
+                        end = i + escaped.len_utf8();
+                    }
+                    None => return Err("Unterminated escape at the end of the line".to_string()),
+                },
+                c => text.push(c),
+            }
+        }
+
+        words.push(Word { text, quoted, start, end });
+    }
+
+    Ok(words)
+}
+
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        c => c,
+    }
+}
+
+// The value made of the words: each word unquoted and unescaped, with the
+// spaces typed between them
+pub fn value(input: &str, words: &[Word]) -> String {
+    let mut value = String::new();
+    for (index, word) in words.iter().enumerate() {
+        if index > 0 {
+            value.push_str(&input[words[index - 1].end..word.start]);
+        }
+        value.push_str(&word.text);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(input: &str) -> Vec<String> {
+        split(input).unwrap().into_iter().map(|word| word.text).collect()
+    }
+
+    #[test]
+    fn splits_at_whitespace() {
+        assert_eq!(texts(r"  insert 5   hello\tworld "), ["insert", "5", "hello\tworld"]);
+        assert!(split("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_spaces() {
+        assert_eq!(texts(r#"insert 1 "a b" 'c d'"#), ["insert", "1", "a b", "c d"]);
+        assert_eq!(texts("say don't"), ["say", "don't"]);
+        assert!(split(r#"insert 1 "a b""#).unwrap()[2].quoted);
+        assert!(!split("insert 1 ttl").unwrap()[2].quoted);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(texts(r#""a\nb" "say \"hi\"" c\ d"#), ["a\nb", "say \"hi\"", "c d"]);
+        assert_eq!(texts(r"a\\b"), [r"a\b"]);
+    }
+
+    #[test]
+    fn unterminated_quotes_and_escapes_fail() {
+        assert!(split(r#"insert 1 "abc"#).is_err());
+        assert!(split("insert 1 'abc").is_err());
+        assert!(split("insert 1 abc\\").is_err());
+    }
+
+    #[test]
+    fn value_of_several_words() {
+        let input = r#"insert 2 'a b'  "c\nd" e"#;
+        let words = split(input).unwrap();
+        assert_eq!(value(input, &words[2..]), "a b  c\nd e");
+        assert_eq!(value(input, &words[2..3]), "a b");
+        assert_eq!(value(input, &[]), "");
+    }
+}
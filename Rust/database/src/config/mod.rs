@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use clap::builder::BoolishValueParser;
+use clap::{Parser, Subcommand};
+
+use crate::btree::{DEFAULT_ORDER, MIN_ORDER};
+use crate::cli::Mode;
+
+// Command-line flags, each of which can also be set by an environment variable
+#[derive(Parser)]
+#[command(
+    name = "database",
+    version,
+    about = "A B-Tree database with a web interface and a command-line interface",
+    after_help = "Exit codes: 0 success, 1 failure, 2 wrong arguments, 3 key or database not found"
+)]
+pub struct Config {
+    /// Run the interactive command-line interface instead of the web server
+    #[arg(long)]
+    pub cli: bool,
+
+    /// Database to open, without the .db extension. The CLI skips asking for
+    /// it and the web server connects to it at startup
+    #[arg(long, env = "RYOU_DB", value_name = "NAME")]
+    pub db: Option<String>,
+
+    /// With --cli, send the commands to the web server at this URL, e.g.
+    /// http://127.0.0.1:8080, instead of opening the database file
+    #[arg(long, env = "RYOU_REMOTE", value_name = "URL")]
+    pub remote: Option<String>,
+
+    /// Directory holding the database files
+    #[arg(long, env = "RYOU_DATA_DIR", value_name = "DIR", default_value = ".", global = true)]
+    pub data_dir: PathBuf,
+
+    /// With --cli, run the commands in this file, e.g. a backup written by
+    /// .dump, then exit instead of showing the prompt
+    #[arg(long, value_name = "FILE", requires = "cli")]
+    pub script: Option<PathBuf>,
+
+    /// Address the web server listens on
+    #[arg(long, env = "RYOU_BIND", value_name = "ADDR", default_value = "127.0.0.1")]
+    pub bind: String,
+
+    /// Port the web server listens on
+    #[arg(long, env = "RYOU_PORT", default_value_t = 8080)]
+    pub port: u16,
+
+    /// Directory of the web interface's files
+    #[arg(long, env = "RYOU_STATIC_DIR", value_name = "DIR", default_value = "./static")]
+    pub static_dir: PathBuf,
+
+    /// Largest number of children of a B-Tree node
+    #[arg(
+        long,
+        env = "RYOU_ORDER",
+        default_value_t = DEFAULT_ORDER,
+        value_parser = parse_order,
+        global = true
+    )]
+    pub order: usize,
+
+    /// Open every database read-only
+    #[arg(long, env = "RYOU_READ_ONLY", value_parser = BoolishValueParser::new(), global = true)]
+    pub read_only: bool,
+
+    /// How records and query results are printed
+    #[arg(long, env = "RYOU_FORMAT", value_enum, global = true)]
+    pub format: Option<Mode>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// One-shot commands run from the shell, e.g. "database get mydb 5"
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print the value of a key
+    Get {
+        db: String,
+        #[arg(allow_negative_numbers = true)]
+        key: i32,
+    },
+    /// Insert or replace a record
+    Put {
+        db: String,
+        #[arg(allow_negative_numbers = true)]
+        key: i32,
+        /// The columns of a row when the database has a schema, otherwise the
+        /// value, separated by spaces
+        #[arg(required = true, allow_hyphen_values = true)]
+        value: Vec<String>,
+    },
+    /// Delete a record
+    Delete {
+        db: String,
+        #[arg(allow_negative_numbers = true)]
+        key: i32,
+    },
+    /// Print the commands that rebuild the database, to restore with --script
+    Dump { db: String },
+    /// Run a query, e.g. "SELECT * WHERE key < 10"
+    Query {
+        db: String,
+        #[arg(required = true, allow_hyphen_values = true)]
+        query: Vec<String>,
+    },
+    /// Run the CLI commands in a file, or read them from standard input
+    Run { db: String, file: Option<PathBuf> },
+}
+
+impl Config {
+    // Path of the file of a database, e.g. "<data dir>/mydb.db"
+    pub fn database_path(&self, db_name: &str) -> String {
+        self.data_dir.join(format!("{}.db", db_name)).to_string_lossy().into_owned()
+    }
+}
+
+fn parse_order(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(order) if order >= MIN_ORDER => Ok(order),
+        _ => Err(format!("the order must be a whole number of at least {}", MIN_ORDER)),
+    }
+}
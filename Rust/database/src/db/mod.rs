@@ -0,0 +1,812 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::btree::{now, BTree, Record};
+use crate::index::{Index, IndexKey};
+use crate::query::Value;
+use crate::schema::Schema;
+use crate::search::TextIndex;
+use crate::storage::{
+    catalog_path, database_stamp, file_stamp, load_catalog, load_expiries, load_history, load_indexes,
+    load_records, load_schema, remove_history, remove_table_files, save_catalog, save_expiries,
+    save_history, save_indexes, save_records, save_schema, table_path, DbLock, FileStamp,
+};
+
+// Number of recent changes kept so that watchers can catch up after
+// reconnecting
+const MAX_CHANGES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+// A write to the database, numbered in the order it happened
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub seq: u64,
+    // The table written to, MAIN_TABLE for the database's own records
+    pub table: String,
+    pub kind: ChangeKind,
+    pub key: i32,
+    // None for deletes
+    pub value: Option<String>,
+    // The value it replaced, None for inserts
+    pub previous: Option<String>,
+    // When the value expires, in seconds since the epoch, if it does
+    pub expires_at: Option<u64>,
+    // When the value it replaced was to expire
+    pub previous_expires_at: Option<u64>,
+}
+
+// How a write treats the key it writes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteMode {
+    // Fails with ErrorKind::AlreadyExists if the key is taken
+    Insert,
+    // Fails with ErrorKind::NotFound if the key does not exist
+    Update,
+    // Inserts the key or overwrites it
+    Upsert,
+}
+
+// One write in a batch passed to Database::write_all
+#[derive(Debug, Clone)]
+pub enum Write {
+    // Expiring at `expires_at`, in seconds since the epoch, if given
+    Put { key: i32, value: String, mode: WriteMode, expires_at: Option<u64> },
+    Delete { key: i32 },
+}
+
+// Called with every change made through a database or one of its tables
+pub type ChangeListener = Box<dyn Fn(&Change) + Send>;
+
+// The recent changes of a database and of its tables, numbered in one sequence
+#[derive(Default)]
+struct ChangeLog {
+    changes: VecDeque<Change>,
+    last_seq: u64,
+    listener: Option<ChangeListener>,
+}
+
+// Name under which the records of the database itself can be used as a table
+pub const MAIN_TABLE: &str = "main";
+
+// A database file loaded into a B-Tree. Writes go through this type so that
+// they are persisted straight away and refused when the file is opened
+// read-only. If another program changes the file, the next write (or a call
+// to reload_if_changed) loads those changes first instead of overwriting them.
+//
+// Besides its own records a database can hold named tables. Each table is
+// stored like a database of its own in a file listed in the catalog.
+pub struct Database {
+    tree: BTree,
+    // When set, every value is a row of these columns
+    schema: Option<Schema>,
+    // Secondary indexes, kept in sync with every write
+    indexes: Vec<Index>,
+    // The words of every record, for full-text search
+    text_index: TextIndex,
+    file_path: String,
+    // None when opened read-only
+    lock: Option<DbLock>,
+    // The file and the files next to it as of the last load or save
+    stamp: Vec<Option<FileStamp>>,
+    // Its name as a table, MAIN_TABLE for the database itself
+    table: String,
+    // Shared with the tables of the database
+    log: Arc<Mutex<ChangeLog>>,
+    tables: BTreeMap<String, Database>,
+    // The catalog as of the last load or save
+    catalog_stamp: Option<FileStamp>,
+}
+
+impl Database {
+    // Open a database, taking the lock on it unless `read_only` is set. Fails
+    // with ErrorKind::WouldBlock if another process holds the lock.
+    pub fn open(file_path: &str, read_only: bool) -> io::Result<Database> {
+        let log = Arc::new(Mutex::new(ChangeLog::default()));
+        let mut db = Self::open_file(file_path, read_only, MAIN_TABLE, log)?;
+        db.load_tables()?;
+        Ok(db)
+    }
+
+    fn open_file(
+        file_path: &str,
+        read_only: bool,
+        table: &str,
+        log: Arc<Mutex<ChangeLog>>,
+    ) -> io::Result<Database> {
+        let lock = if read_only {
+            None
+        } else {
+            Some(DbLock::acquire(file_path)?)
+        };
+
+        let stamp = database_stamp(file_path)?;
+        let (tree, schema) = Self::load(file_path)?;
+
+        let mut db = Database {
+            tree,
+            schema,
+            indexes: Vec::new(),
+            text_index: TextIndex::new(),
+            file_path: file_path.to_string(),
+            lock,
+            stamp,
+            table: table.to_string(),
+            log,
+            tables: BTreeMap::new(),
+            catalog_stamp: None,
+        };
+        db.load_indexes()?;
+        Ok(db)
+    }
+
+    // Open the tables listed in the catalog, keeping those already open
+    fn load_tables(&mut self) -> io::Result<()> {
+        let catalog = load_catalog(&self.file_path)?;
+        let mut tables = BTreeMap::new();
+        for (name, table_file) in catalog {
+            let table = match self.tables.remove(&name) {
+                Some(table) => table,
+                None => Self::open_file(&table_file, self.is_read_only(), &name, self.log.clone())?,
+            };
+            tables.insert(name, table);
+        }
+
+        self.tables = tables;
+        self.catalog_stamp = file_stamp(&catalog_path(&self.file_path))?;
+        Ok(())
+    }
+
+    fn load(file_path: &str) -> io::Result<(BTree, Option<Schema>)> {
+        let mut tree = BTree::new();
+        for record in load_records(file_path)? {
+            tree.insert(record.key, record.value);
+        }
+
+        for (key, expires_at) in load_expiries(file_path)? {
+            tree.expire_at(key, expires_at);
+        }
+
+        if let Some(versions) = load_history(file_path)? {
+            tree.restore_history(versions);
+        }
+
+        Ok((tree, load_schema(file_path)?))
+    }
+
+    // Reload the database if its file, or one of the files next to it, was
+    // changed by another program since we last read or wrote it. The differences are recorded as changes. Returns
+    // whether a reload happened.
+    pub fn reload_if_changed(&mut self) -> io::Result<bool> {
+        let catalog_changed = file_stamp(&catalog_path(&self.file_path))? != self.catalog_stamp;
+        if catalog_changed {
+            self.load_tables()?;
+        }
+
+        let stamp = database_stamp(&self.file_path)?;
+        if stamp == self.stamp {
+            return Ok(catalog_changed);
+        }
+
+        let (tree, schema) = Self::load(&self.file_path)?;
+        let old_expiries: HashMap<i32, u64> = self.tree.expiries().into_iter().collect();
+        let old: HashMap<i32, String> = self
+            .tree
+            .get_all_records()
+            .into_iter()
+            .map(|r| (r.key, r.value))
+            .collect();
+        let new = tree.get_all_records();
+
+        self.tree = tree;
+        self.schema = schema;
+        self.stamp = stamp;
+        self.load_indexes()?;
+
+        for record in &new {
+            match old.get(&record.key) {
+                Some(value) if *value == record.value => {}
+                previous => {
+                    let previous_expires_at = old_expiries.get(&record.key).copied();
+                    let value = Some(record.value.clone());
+                    self.record_change(record.key, value, previous.cloned(), previous_expires_at)
+                }
+            }
+        }
+
+        let mut deleted: Vec<(i32, String)> = old.into_iter().collect();
+        deleted.retain(|(key, _)| self.tree.search(*key).is_none());
+        deleted.sort();
+        for (key, previous) in deleted {
+            self.record_change(key, None, Some(previous), old_expiries.get(&key).copied());
+        }
+
+        Ok(true)
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn tree(&self) -> &BTree {
+        &self.tree
+    }
+
+    pub fn schema(&self) -> Option<&Schema> {
+        self.schema.as_ref()
+    }
+
+    // Give the database a schema, or remove it with None. Fails if an
+    // existing record is not a valid row of the new schema.
+    pub fn set_schema(&mut self, schema: Option<Schema>) -> io::Result<()> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+
+        if let Some(index) = self.indexes.iter().find(|index| match &schema {
+            Some(schema) => schema.index_of(index.column()).is_none(),
+            None => index.column() != "value",
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Column '{}' is indexed, drop the index first", index.column()),
+            ));
+        }
+
+        if let Some(schema) = &schema {
+            for record in self.tree.get_all_records() {
+                schema.decode(&record.value).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Record {} does not fit the schema: {}", record.key, e),
+                    )
+                })?;
+            }
+        }
+
+        save_schema(&self.file_path, schema.as_ref())?;
+        self.schema = schema;
+        self.rebuild_indexes();
+        self.stamp = database_stamp(&self.file_path)?;
+        Ok(())
+    }
+
+    pub fn indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    pub fn index(&self, column: &str) -> Option<&Index> {
+        self.indexes.iter().find(|index| index.column() == column)
+    }
+
+    // Index a column: "value" without a schema, one of its columns with one
+    pub fn create_index(&mut self, column: &str) -> io::Result<()> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+
+        let exists = match &self.schema {
+            Some(schema) => schema.index_of(column).is_some(),
+            None => column == "value",
+        };
+        if !exists {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unknown column '{}'", column),
+            ));
+        }
+        if self.index(column).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Column '{}' is already indexed", column),
+            ));
+        }
+
+        self.indexes.push(Index::new(column));
+        self.rebuild_indexes();
+        self.save_indexes()
+    }
+
+    // Records containing words starting with each of the terms, best match
+    // first, with their score
+    pub fn search(&self, terms: &str) -> Vec<(Record, f64)> {
+        self.text_index
+            .search(terms)
+            .into_iter()
+            .filter_map(|(key, score)| {
+                let value = self.tree.search(key)?;
+                Some((Record { key, value }, score))
+            })
+            .collect()
+    }
+
+    pub fn drop_index(&mut self, column: &str) -> io::Result<()> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+
+        let count = self.indexes.len();
+        self.indexes.retain(|index| index.column() != column);
+        if self.indexes.len() == count {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Column '{}' is not indexed", column),
+            ));
+        }
+        self.save_indexes()
+    }
+
+    // Names of the tables in the database, not counting the main table
+    pub fn table_names(&self) -> Vec<String> {
+        self.tables.keys().cloned().collect()
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Database> {
+        if name == MAIN_TABLE {
+            return Some(self);
+        }
+        self.tables.get(name)
+    }
+
+    pub fn table_mut(&mut self, name: &str) -> Option<&mut Database> {
+        if name == MAIN_TABLE {
+            return Some(self);
+        }
+        self.tables.get_mut(name)
+    }
+
+    // Add an empty table, with rows of `schema` if given
+    pub fn create_table(&mut self, name: &str, schema: Option<Schema>) -> io::Result<()> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+
+        let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid table name '{}', use letters, digits and underscores", name),
+            ));
+        }
+        if name == MAIN_TABLE || self.tables.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Table '{}' already exists", name),
+            ));
+        }
+
+        // Clear out files left behind by a table of the same name that was
+        // dropped by hand
+        let table_file = table_path(&self.file_path, name);
+        remove_table_files(&table_file)?;
+
+        let mut table = Self::open_file(&table_file, false, name, self.log.clone())?;
+        table.set_schema(schema)?;
+        table.save()?;
+
+        self.tables.insert(name.to_string(), table);
+        self.save_catalog()
+    }
+
+    // Remove a table and delete its records
+    pub fn drop_table(&mut self, name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+
+        let table = match self.tables.remove(name) {
+            Some(table) => table,
+            None if name == MAIN_TABLE => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Table '{}' cannot be dropped", MAIN_TABLE),
+                ))
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Table '{}' not found", name),
+                ))
+            }
+        };
+
+        self.save_catalog()?;
+        let table_file = table.file_path.clone();
+        // Release the table's lock before deleting its files
+        drop(table);
+        remove_table_files(&table_file)
+    }
+
+    fn save_catalog(&mut self) -> io::Result<()> {
+        let catalog: Vec<(String, String)> = self
+            .tables
+            .iter()
+            .map(|(name, table)| (name.clone(), table.file_path.clone()))
+            .collect();
+        save_catalog(&self.file_path, &catalog)?;
+        self.catalog_stamp = file_stamp(&catalog_path(&self.file_path))?;
+        Ok(())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    // Write a key as `mode` allows, returning the value it replaced. Given a
+    // TTL, the key expires that many seconds from now.
+    pub fn put(
+        &mut self,
+        key: i32,
+        value: String,
+        ttl: Option<u64>,
+        mode: WriteMode,
+    ) -> io::Result<Option<String>> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+        self.check_row(&value)?;
+        check_mode(key, self.tree.search(key).is_some(), mode)?;
+        let expires_at = match ttl {
+            Some(ttl) => Some(now().checked_add(ttl).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("TTL {} is too large", ttl))
+            })?),
+            None => None,
+        };
+        let previous_expires_at = self.tree.expires_at(key);
+        let previous = self.tree.insert(key, value.clone());
+        self.update_indexes(key, Some(&value));
+        if let Some(expires_at) = expires_at {
+            self.tree.expire_at(key, expires_at);
+        }
+        self.save()?;
+        self.record_change(key, Some(value), previous.clone(), previous_expires_at);
+        Ok(previous)
+    }
+
+    pub fn delete(&mut self, key: i32) -> io::Result<bool> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+        let previous = self.tree.search(key);
+        let previous_expires_at = self.tree.expires_at(key);
+        let deleted = self.tree.delete(key);
+        self.update_indexes(key, None);
+        if deleted {
+            self.save()?;
+            self.record_change(key, None, previous, previous_expires_at);
+        }
+        Ok(deleted)
+    }
+
+    // Apply several writes and save once at the end. Nothing is written if
+    // any of them fails. Returns how many records were inserted, updated or
+    // deleted.
+    pub fn write_all(&mut self, writes: Vec<Write>) -> io::Result<usize> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+
+        // Whether each key exists after the writes before it in the batch
+        let mut exists: HashMap<i32, bool> = HashMap::new();
+        for write in &writes {
+            match write {
+                Write::Put { key, value, mode, .. } => {
+                    self.check_row(value)?;
+                    let found = exists.get(key).copied().unwrap_or_else(|| self.tree.search(*key).is_some());
+                    check_mode(*key, found, *mode)?;
+                    exists.insert(*key, true);
+                }
+                Write::Delete { key } => {
+                    exists.insert(*key, false);
+                }
+            }
+        }
+
+        let mut changes = Vec::new();
+        for write in writes {
+            match write {
+                Write::Put { key, value, expires_at, .. } => {
+                    let previous_expires_at = self.tree.expires_at(key);
+                    let previous = self.tree.insert(key, value.clone());
+                    if let Some(expires_at) = expires_at {
+                        self.tree.expire_at(key, expires_at);
+                    }
+                    self.update_indexes(key, Some(&value));
+                    changes.push((key, Some(value), previous, previous_expires_at));
+                }
+                Write::Delete { key } => {
+                    let previous = self.tree.search(key);
+                    let previous_expires_at = self.tree.expires_at(key);
+                    let deleted = self.tree.delete(key);
+                    self.update_indexes(key, None);
+                    if deleted {
+                        changes.push((key, None, previous, previous_expires_at));
+                    }
+                }
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok(0);
+        }
+        self.save()?;
+
+        let count = changes.len();
+        for (key, value, previous, previous_expires_at) in changes {
+            self.record_change(key, value, previous, previous_expires_at);
+        }
+        Ok(count)
+    }
+
+    // Delete records whose TTL has passed, returning how many were removed
+    pub fn purge_expired(&mut self) -> io::Result<usize> {
+        self.reload_if_changed()?;
+        let expired = self.tree.expired_keys();
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        self.check_writable()?;
+
+        let mut previous = Vec::new();
+        for &key in &expired {
+            previous.push((self.tree.search(key), self.tree.expires_at(key)));
+            self.tree.delete(key);
+            self.update_indexes(key, None);
+        }
+        self.save()?;
+
+        for (&key, (previous, previous_expires_at)) in expired.iter().zip(previous) {
+            self.record_change(key, None, previous, previous_expires_at);
+        }
+        Ok(expired.len())
+    }
+
+    pub fn set_history(&mut self, enabled: bool) -> io::Result<()> {
+        self.check_writable()?;
+        self.reload_if_changed()?;
+        if enabled {
+            self.tree.enable_history();
+            self.save()
+        } else {
+            self.tree.disable_history();
+            remove_history(&self.file_path)?;
+            self.stamp = database_stamp(&self.file_path)?;
+            Ok(())
+        }
+    }
+
+    // Sequence number of the latest change to the database or any of its
+    // tables, 0 if nothing has changed yet
+    pub fn last_seq(&self) -> u64 {
+        self.log.lock().unwrap().last_seq
+    }
+
+    // Changes to the database and its tables made after `seq`, or None if they
+    // are no longer all kept
+    pub fn changes_since(&self, seq: u64) -> Option<Vec<Change>> {
+        let log = self.log.lock().unwrap();
+        if seq > log.last_seq {
+            return None;
+        }
+
+        let oldest = log.changes.front().map_or(log.last_seq + 1, |c| c.seq);
+        if seq + 1 < oldest {
+            return None;
+        }
+
+        Some(log.changes.iter().filter(|c| c.seq > seq).cloned().collect())
+    }
+
+    // Call `listener` after every change made through this database or its
+    // tables, those created later included
+    pub fn on_change(&mut self, listener: ChangeListener) {
+        self.log.lock().unwrap().listener = Some(listener);
+    }
+
+    // Note that `key` was set to `value`, or deleted with None, replacing
+    // `previous` and its expiry
+    fn record_change(
+        &mut self,
+        key: i32,
+        value: Option<String>,
+        previous: Option<String>,
+        previous_expires_at: Option<u64>,
+    ) {
+        let expires_at = value.as_ref().and(self.tree.expires_at(key));
+        let kind = match (&value, &previous) {
+            (None, _) => ChangeKind::Delete,
+            (Some(_), None) => ChangeKind::Insert,
+            (Some(_), Some(_)) => ChangeKind::Update,
+        };
+        let mut log = self.log.lock().unwrap();
+        log.last_seq += 1;
+        let change = Change {
+            seq: log.last_seq,
+            table: self.table.clone(),
+            kind,
+            key,
+            value,
+            previous,
+            expires_at,
+            previous_expires_at,
+        };
+
+        if let Some(listener) = &log.listener {
+            listener(&change);
+        }
+
+        log.changes.push_back(change);
+        if log.changes.len() > MAX_CHANGES {
+            log.changes.pop_front();
+        }
+    }
+
+    // Point the indexes at the new value of a key, or drop it from them
+    fn update_indexes(&mut self, key: i32, value: Option<&str>) {
+        for index in &mut self.indexes {
+            match value.and_then(|value| index_key(self.schema.as_ref(), index.column(), value)) {
+                Some(indexed) => index.insert(key, indexed),
+                None => index.remove(key),
+            }
+        }
+
+        match value {
+            Some(value) => self.text_index.insert(key, &searchable_text(self.schema.as_ref(), value)),
+            None => self.text_index.remove(key),
+        }
+    }
+
+    // Read the indexed columns from disk and index the current records
+    fn load_indexes(&mut self) -> io::Result<()> {
+        self.indexes = load_indexes(&self.file_path)?
+            .iter()
+            .map(|column| Index::new(column))
+            .collect();
+        self.rebuild_indexes();
+        Ok(())
+    }
+
+    fn rebuild_indexes(&mut self) {
+        let columns: Vec<String> = self.indexes.iter().map(|i| i.column().to_string()).collect();
+        self.indexes = columns.iter().map(|column| Index::new(column)).collect();
+        self.text_index = TextIndex::new();
+        for record in self.tree.get_all_records() {
+            self.update_indexes(record.key, Some(&record.value));
+        }
+    }
+
+    fn save_indexes(&mut self) -> io::Result<()> {
+        let columns: Vec<String> = self.indexes.iter().map(|i| i.column().to_string()).collect();
+        save_indexes(&self.file_path, &columns)?;
+        self.stamp = database_stamp(&self.file_path)?;
+        Ok(())
+    }
+
+    // Persist the records and their expiry times, and the versions when
+    // history is on
+    fn save(&mut self) -> io::Result<()> {
+        save_records(&self.file_path, &self.tree.get_all_records())?;
+        save_expiries(&self.file_path, &self.tree.expiries())?;
+        if self.tree.history_enabled() {
+            save_history(&self.file_path, &self.tree.all_versions())?;
+        }
+        self.stamp = database_stamp(&self.file_path)?;
+        Ok(())
+    }
+
+    // With a schema, values must be valid rows
+    fn check_row(&self, value: &str) -> io::Result<()> {
+        match &self.schema {
+            Some(schema) => schema
+                .decode(value)
+                .map(|_| ())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Database '{}' is open read-only", self.file_path),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Refuse a write that `mode` does not allow on a key that does or does not
+// exist
+fn check_mode(key: i32, exists: bool, mode: WriteMode) -> io::Result<()> {
+    match mode {
+        WriteMode::Insert if exists => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Key {} already exists", key),
+        )),
+        WriteMode::Update if !exists => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Key {} not found", key),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// The text searched for the words of a record: the value, or the text
+// columns of a row
+fn searchable_text(schema: Option<&Schema>, value: &str) -> String {
+    match schema {
+        Some(schema) => {
+            let cells = schema.decode(value).unwrap_or_default();
+            let texts: Vec<String> = cells
+                .into_iter()
+                .filter_map(|cell| match cell {
+                    Value::Text(text) => Some(text),
+                    _ => None,
+                })
+                .collect();
+            texts.join(" ")
+        }
+        None => value.to_string(),
+    }
+}
+
+// The value a record is indexed under for a column, None if it has no such
+// column
+fn index_key(schema: Option<&Schema>, column: &str, value: &str) -> Option<IndexKey> {
+    match schema {
+        Some(schema) => {
+            let index = schema.index_of(column)?;
+            let cells = schema.decode(value).ok()?;
+            Some(IndexKey::from(&cells[index]))
+        }
+        None if column == "value" => Some(IndexKey::Text(value.to_string())),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_ttl_past_the_end_of_time_is_refused() {
+        let dir = std::env::temp_dir().join(format!("ryou-db-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ttl.db").to_string_lossy().into_owned();
+        let mut db = Database::open(&path, false).unwrap();
+
+        let error = db.put(1, "one".to_string(), Some(u64::MAX), WriteMode::Insert).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(db.tree().search(1), None);
+
+        db.put(1, "one".to_string(), Some(60), WriteMode::Insert).unwrap();
+        assert!(db.tree().expires_at(1).is_some_and(|at| at > now()));
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn edits_to_the_files_next_to_the_database_are_picked_up() {
+        let dir = std::env::temp_dir().join(format!("ryou-db-sidecar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("side.db").to_string_lossy().into_owned();
+        let mut db = Database::open(&path, false).unwrap();
+        db.put(1, "one".to_string(), None, WriteMode::Insert).unwrap();
+
+        // Another program gives key 1 a TTL and indexes the values
+        let expires_at = now() + 600;
+        save_expiries(&path, &[(1, expires_at)]).unwrap();
+        save_indexes(&path, &["value".to_string()]).unwrap();
+
+        assert!(db.reload_if_changed().unwrap());
+        assert_eq!(db.tree().expires_at(1), Some(expires_at));
+        assert!(db.index("value").is_some());
+
+        // A later write keeps them
+        db.put(2, "two".to_string(), None, WriteMode::Insert).unwrap();
+        let expiries = load_expiries(&path).unwrap();
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(expiries, [(1, expires_at)]);
+    }
+}
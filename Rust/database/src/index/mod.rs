@@ -0,0 +1,135 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use crate::btree::{Trace, Tree};
+use crate::query::Value;
+
+// A cell as stored in an index. Unlike Value it has a total order, so that it
+// can be used as a B-Tree key: NULL sorts first, floats use their total order.
+#[derive(Debug, Clone)]
+pub enum IndexKey {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl IndexKey {
+    fn rank(&self) -> u8 {
+        match self {
+            IndexKey::Null => 0,
+            IndexKey::Bool(_) => 1,
+            IndexKey::Int(_) => 2,
+            IndexKey::Float(_) => 3,
+            IndexKey::Text(_) => 4,
+        }
+    }
+}
+
+impl From<&Value> for IndexKey {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null | Value::Param(_) => IndexKey::Null,
+            Value::Bool(flag) => IndexKey::Bool(*flag),
+            Value::Int(number) => IndexKey::Int(*number),
+            Value::Float(number) => IndexKey::Float(*number),
+            Value::Text(text) => IndexKey::Text(text.clone()),
+        }
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (IndexKey::Bool(a), IndexKey::Bool(b)) => a.cmp(b),
+            (IndexKey::Int(a), IndexKey::Int(b)) => a.cmp(b),
+            (IndexKey::Float(a), IndexKey::Float(b)) => a.total_cmp(b),
+            (IndexKey::Text(a), IndexKey::Text(b)) => a.cmp(b),
+            (a, b) => a.rank().cmp(&b.rank()),
+        }
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for IndexKey {}
+
+// A secondary index on one column: a second B-Tree mapping the column's value
+// to the keys of the records holding it. Entries are (value, key) pairs so
+// that records sharing a value each get their own entry.
+pub struct Index {
+    column: String,
+    entries: Tree<(IndexKey, i32), ()>,
+    // The indexed value of each key, to find its entry again on update
+    values: HashMap<i32, IndexKey>,
+}
+
+impl Index {
+    pub fn new(column: &str) -> Self {
+        Index {
+            column: column.to_string(),
+            entries: Tree::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn column(&self) -> &str {
+        &self.column
+    }
+
+    // Index a record under its current value, replacing any earlier entry
+    pub fn insert(&mut self, key: i32, value: IndexKey) {
+        self.remove(key);
+        self.entries.insert((value.clone(), key), ());
+        self.values.insert(key, value);
+    }
+
+    pub fn remove(&mut self, key: i32) {
+        if let Some(value) = self.values.remove(&key) {
+            self.entries.delete(&(value, key));
+        }
+    }
+
+    // Keys of the records whose value lies between the bounds, ordered by
+    // value. NULLs are only returned when a bound asks for them. The nodes
+    // visited are recorded in `trace`.
+    pub fn lookup(&self, start: Bound<IndexKey>, end: Bound<IndexKey>, trace: &mut Trace) -> Vec<i32> {
+        let start = match start {
+            Bound::Included(value) => Bound::Included((value, i32::MIN)),
+            Bound::Excluded(value) => Bound::Excluded((value, i32::MAX)),
+            Bound::Unbounded => Bound::Excluded((IndexKey::Null, i32::MAX)),
+        };
+        let end = match end {
+            Bound::Included(value) => Bound::Included((value, i32::MAX)),
+            Bound::Excluded(value) => Bound::Excluded((value, i32::MIN)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        self.entries
+            .range_traced((start, end), trace)
+            .into_iter()
+            .map(|((_, key), _)| key)
+            .collect()
+    }
+
+    // Number of different values in the index, for estimating how many
+    // records share one
+    pub fn distinct_values(&self) -> usize {
+        let mut values: Vec<&IndexKey> = self.values.values().collect();
+        values.sort();
+        values.dedup();
+        values.len()
+    }
+}
@@ -1,17 +1,32 @@
 mod btree;
 mod cli;
+mod config;
+mod db;
+mod index;
+mod query;
+mod schema;
+mod search;
 mod storage;
 mod web;
 
+use clap::Parser;
+
+use config::Config;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Add command line argument parsing
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 && args[1] == "--cli" {
+    // Flags and environment variables; --help and --version exit here
+    let mut config = Config::parse();
+    btree::set_order(config.order);
+
+    if let Some(command) = config.command.take() {
+        // One-shot commands such as "database get mydb 5" for scripts
+        std::process::exit(cli::run_subcommand(command, &config))
+    } else if config.cli {
         // Run in CLI mode if requested
-        cli::start_cli()
+        cli::start_cli(&config)
     } else {
         // Otherwise start the web server
-        web::start_server().await
+        web::start_server(config).await
     }
 }
\ No newline at end of file
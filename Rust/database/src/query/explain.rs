@@ -0,0 +1,171 @@
+use std::ops::Bound;
+
+use serde::Serialize;
+
+use crate::btree::Trace;
+use crate::db::Database;
+
+use super::{
+    candidates, check, key_bounds, limits_key_only, matches, select_rows, Column, CompareOp, Condition, Layout, QueryError,
+    Scan, Statement,
+};
+
+// Share of records guessed to match a condition the statistics say nothing
+// about: an equality on a column without an index, LIKE or IS NULL
+const DEFAULT_EQ_SELECTIVITY: f64 = 0.1;
+
+// Share of records guessed to fall in a range of a column other than the key
+const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+// How a statement finds its records: the access path, the nodes visited on
+// each level of the B-Tree, and the rows expected before running it against
+// the rows it actually matched
+#[derive(Debug, Serialize)]
+pub struct Explain {
+    pub access_path: String,
+    // Records stored in the table
+    pub records: usize,
+    // Work done in the record tree
+    pub tree: Trace,
+    // Work done in the index, for index scans
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<Trace>,
+    pub estimated_rows: usize,
+    pub actual_rows: usize,
+}
+
+// Explain a SELECT, an aggregate, UPDATE or DELETE on the table it targets.
+// The records are read to count the actual rows, but nothing is written.
+pub fn explain(db: &Database, statement: &Statement) -> Result<Explain, QueryError> {
+    let layout = Layout { schema: db.schema() };
+    let filter = match statement {
+        Statement::Select(select) => select.filter.as_ref(),
+        Statement::Aggregate { filter, .. }
+        | Statement::Update { filter, .. }
+        | Statement::Delete { filter, .. } => filter.as_ref(),
+        Statement::Join(_) => return Err(QueryError::Invalid("JOIN cannot be explained".to_string())),
+        _ => {
+            return Err(QueryError::Invalid(
+                "Only SELECT, aggregates, UPDATE and DELETE can be explained".to_string(),
+            ))
+        }
+    };
+    if let Some(filter) = filter {
+        check(layout, filter)?;
+    }
+
+    let mut scan = Scan::default();
+    let records = match filter {
+        Some(filter) => candidates(db, filter, &mut scan),
+        None => db.tree().range_traced(.., &mut scan.tree),
+    };
+
+    let mut estimated_rows = estimate(db, filter);
+    let mut actual_rows = 0;
+    for record in records {
+        let row = layout.row(record)?;
+        if filter.is_none_or(|filter| matches(layout, filter, &row)) {
+            actual_rows += 1;
+        }
+    }
+
+    // LIMIT and OFFSET only cut the rows returned, not the records read
+    if let Statement::Select(select) = statement {
+        let limit = select.limit.unwrap_or(usize::MAX);
+        estimated_rows = estimated_rows.saturating_sub(select.offset).min(limit);
+        actual_rows = select_rows(db, layout, select)?.len();
+    }
+
+    Ok(Explain {
+        access_path: scan.path.to_string(),
+        records: db.tree().len(),
+        tree: scan.tree,
+        index: scan.index,
+        estimated_rows,
+        actual_rows,
+    })
+}
+
+// Rows a filter is expected to match, from the number of records, the range
+// of keys and the number of distinct values in each index
+fn estimate(db: &Database, filter: Option<&Condition>) -> usize {
+    let records = db.tree().len();
+    match filter {
+        Some(filter) => (records as f64 * selectivity(db, filter)).round() as usize,
+        None => records,
+    }
+}
+
+// Share of the records a condition is expected to match
+fn selectivity(db: &Database, condition: &Condition) -> f64 {
+    match condition {
+        Condition::Compare {
+            column: Column::Key,
+            op: CompareOp::Ne,
+            value,
+        } => {
+            let equal = Condition::Compare {
+                column: Column::Key,
+                op: CompareOp::Eq,
+                value: value.clone(),
+            };
+            1.0 - selectivity(db, &equal)
+        }
+        Condition::Compare { column: Column::Key, .. } | Condition::Between { column: Column::Key, .. } => {
+            key_selectivity(db, key_bounds(condition))
+        }
+        Condition::Compare {
+            column: Column::Named(name),
+            op,
+            ..
+        } => {
+            let equal = match db.index(name) {
+                Some(index) => 1.0 / index.distinct_values().max(1) as f64,
+                None => DEFAULT_EQ_SELECTIVITY,
+            };
+            match op {
+                CompareOp::Eq => equal,
+                CompareOp::Ne => 1.0 - equal,
+                _ => DEFAULT_RANGE_SELECTIVITY,
+            }
+        }
+        Condition::Between { .. } => DEFAULT_RANGE_SELECTIVITY,
+        Condition::Like { .. } | Condition::IsNull { .. } => DEFAULT_EQ_SELECTIVITY,
+        // Limits on the key combine into one range rather than being
+        // independent of each other
+        Condition::And(..) if limits_key_only(condition) => key_selectivity(db, key_bounds(condition)),
+        Condition::And(a, b) => selectivity(db, a) * selectivity(db, b),
+        Condition::Or(a, b) => {
+            let (a, b) = (selectivity(db, a), selectivity(db, b));
+            a + b - a * b
+        }
+        Condition::Not(condition) => 1.0 - selectivity(db, condition),
+    }
+}
+
+// Share of the records with keys between the bounds, assuming the keys are
+// spread evenly between the smallest and the largest
+fn key_selectivity(db: &Database, (start, end): (Bound<i32>, Bound<i32>)) -> f64 {
+    let records = db.tree().len();
+    let (first, last) = match (db.tree().first(), db.tree().last()) {
+        (Some(first), Some(last)) if records > 0 => (first.key as i64, last.key as i64),
+        _ => return 0.0,
+    };
+
+    let low = match start {
+        Bound::Included(key) => key as i64,
+        Bound::Excluded(key) => key as i64 + 1,
+        Bound::Unbounded => first,
+    };
+    let high = match end {
+        Bound::Included(key) => key as i64,
+        Bound::Excluded(key) => key as i64 - 1,
+        Bound::Unbounded => last,
+    };
+    let (low, high) = (low.max(first), high.min(last));
+    if low > high {
+        return 0.0;
+    }
+
+    (high - low + 1) as f64 / (last - first + 1) as f64
+}
@@ -0,0 +1,1273 @@
+mod explain;
+mod parser;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
+use std::ops::Bound;
+
+use serde::Serialize;
+
+use crate::btree::{now, Record, Trace};
+use crate::db::{Database, Write, WriteMode, MAIN_TABLE};
+use crate::index::IndexKey;
+use crate::schema::{ColumnDef, ColumnType, Schema};
+
+pub use explain::{explain, Explain};
+pub use parser::{parse, parse_columns};
+use parser::parse_with_params;
+
+#[derive(Debug)]
+pub enum QueryError {
+    // The query could not be parsed
+    Syntax(String),
+    // The query parsed but cannot be run, e.g. comparing the key with text
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::Syntax(message) => write!(f, "Syntax error: {}", message),
+            QueryError::Invalid(message) => write!(f, "{}", message),
+            QueryError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<io::Error> for QueryError {
+    fn from(error: io::Error) -> Self {
+        QueryError::Io(error)
+    }
+}
+
+// A literal in a query, or a cell in a result row
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bool(bool),
+    Null,
+    // The nth ? placeholder of a prepared statement, replaced by its value
+    // before the statement runs
+    #[serde(skip)]
+    Param(usize),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(number) => write!(f, "{}", number),
+            // Debug keeps the decimal point, e.g. 3.0
+            Value::Float(number) => write!(f, "{:?}", number),
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Bool(flag) => write!(f, "{}", flag),
+            Value::Null => write!(f, "NULL"),
+            Value::Param(_) => write!(f, "?"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Key,
+    // A column of the schema, or "value" in a database without one
+    Named(String),
+}
+
+impl Column {
+    pub fn name(&self) -> &str {
+        match self {
+            Column::Key => "key",
+            Column::Named(name) => name,
+        }
+    }
+}
+
+// How the cells of a record are laid out: the columns of the schema, or a
+// single text column called "value" in a database without a schema
+#[derive(Clone, Copy)]
+struct Layout<'a> {
+    schema: Option<&'a Schema>,
+}
+
+impl Layout<'_> {
+    fn names(&self) -> Vec<String> {
+        match self.schema {
+            Some(schema) => schema.names(),
+            None => vec!["value".to_string()],
+        }
+    }
+
+    fn index_of(&self, column: &Column) -> Result<Option<usize>, QueryError> {
+        let index = match (column, self.schema) {
+            (Column::Key, _) => return Ok(None),
+            (Column::Named(name), Some(schema)) => schema.index_of(name),
+            (Column::Named(name), None) => Some(0).filter(|_| name == "value"),
+        };
+        match index {
+            Some(index) => Ok(Some(index)),
+            None => Err(QueryError::Invalid(format!("Unknown column '{}'", column.name()))),
+        }
+    }
+
+    fn row(&self, record: Record) -> Result<Row, QueryError> {
+        let cells = match self.schema {
+            Some(schema) => schema.decode(&record.value).map_err(QueryError::Invalid)?,
+            None => vec![Value::Text(record.value)],
+        };
+        Ok(Row {
+            key: record.key,
+            cells,
+        })
+    }
+
+    fn encode(&self, cells: &[Value]) -> Result<String, QueryError> {
+        match (self.schema, cells) {
+            (Some(schema), _) => schema.encode(cells).map_err(QueryError::Invalid),
+            (None, [Value::Null]) => Err(QueryError::Invalid("The value cannot be NULL".to_string())),
+            (None, [value]) => Ok(value.to_string()),
+            (None, _) => Err(QueryError::Invalid(format!("Expected 1 column but got {}", cells.len()))),
+        }
+    }
+}
+
+// A record split into its key and cells
+struct Row {
+    key: i32,
+    cells: Vec<Value>,
+}
+
+impl Row {
+    fn get(&self, index: Option<usize>) -> Value {
+        match index {
+            Some(index) => self.cells[index].clone(),
+            None => Value::Int(self.key as i64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Compare { column: Column, op: CompareOp, value: Value },
+    Between { column: Column, low: Value, high: Value },
+    // % matches any run of characters, _ matches a single character
+    Like { column: Column, pattern: String },
+    IsNull { column: Column },
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    fn values_mut<'a>(&'a mut self, values: &mut Vec<&'a mut Value>) {
+        match self {
+            Condition::Compare { value, .. } => values.push(value),
+            Condition::Between { low, high, .. } => {
+                values.push(low);
+                values.push(high);
+            }
+            Condition::Like { .. } | Condition::IsNull { .. } => {}
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.values_mut(values);
+                b.values_mut(values);
+            }
+            Condition::Not(a) => a.values_mut(values),
+        }
+    }
+
+    // Every column the condition reads
+    fn columns_mut<'a>(&'a mut self, columns: &mut Vec<&'a mut Column>) {
+        match self {
+            Condition::Compare { column, .. }
+            | Condition::Between { column, .. }
+            | Condition::Like { column, .. }
+            | Condition::IsNull { column } => columns.push(column),
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.columns_mut(columns);
+                b.columns_mut(columns);
+            }
+            Condition::Not(a) => a.columns_mut(columns),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderBy {
+    pub column: Column,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Select {
+    // None for the main table
+    pub table: Option<String>,
+    // Empty for SELECT *
+    pub columns: Vec<Column>,
+    pub filter: Option<Condition>,
+    pub order_by: Option<OrderBy>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    // Keys found in both tables
+    Inner,
+    // Every key of the left table, with NULLs where the right one has none
+    Left,
+}
+
+// A table read by a join: a table of the database the query runs on, or of
+// another loaded database when `database` is set ("main" for the records of
+// that database itself)
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub database: Option<String>,
+    pub table: String,
+}
+
+impl Source {
+    // Name its columns are qualified with, e.g. "orders" in orders.total
+    pub fn label(&self) -> &str {
+        match &self.database {
+            Some(database) if self.table == MAIN_TABLE => database,
+            _ => &self.table,
+        }
+    }
+}
+
+// Two tables paired by key, e.g. SELECT * FROM users LEFT JOIN shop.orders
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub left: Source,
+    pub right: Source,
+    // Columns, filter, order and limit of the joined rows, without a table
+    pub select: Select,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFn {
+    Count,
+    Min,
+    Max,
+    Sum,
+    Avg,
+}
+
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    pub function: AggregateFn,
+    // None for COUNT(*)
+    pub column: Option<Column>,
+}
+
+impl AggregateFn {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregateFn::Count => "COUNT",
+            AggregateFn::Min => "MIN",
+            AggregateFn::Max => "MAX",
+            AggregateFn::Sum => "SUM",
+            AggregateFn::Avg => "AVG",
+        }
+    }
+}
+
+impl Aggregate {
+    // Result column name, e.g. "COUNT(*)" or "MAX(age)"
+    pub fn name(&self) -> String {
+        let column = self.column.as_ref().map_or("*", |c| c.name());
+        format!("{}({})", self.function.name(), column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Select(Select),
+    Join(Join),
+    // Show how the statement finds its records instead of running it
+    Explain(Box<Statement>),
+    Aggregate { table: Option<String>, aggregates: Vec<Aggregate>, filter: Option<Condition> },
+    // Without columns each row holds the key followed by every column. INSERT
+    // fails on a key that exists, UPSERT overwrites it.
+    Insert {
+        table: Option<String>,
+        columns: Option<Vec<Column>>,
+        rows: Vec<Vec<Value>>,
+        upsert: bool,
+        // Seconds until the rows expire
+        ttl: Option<u64>,
+    },
+    Update { table: Option<String>, assignments: Vec<(String, Value)>, filter: Option<Condition> },
+    Delete { table: Option<String>, filter: Option<Condition> },
+    CreateTable { name: String, schema: Option<Schema> },
+    DropTable { name: String },
+    CreateIndex { table: Option<String>, column: String },
+    DropIndex { table: Option<String>, column: String },
+}
+
+impl Statement {
+    fn filter(&self) -> Option<&Condition> {
+        match self {
+            Statement::Select(select) => select.filter.as_ref(),
+            Statement::Explain(statement) => statement.filter(),
+            Statement::Aggregate { filter, .. }
+            | Statement::Update { filter, .. }
+            | Statement::Delete { filter, .. } => filter.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Every literal in the statement
+    fn values_mut(&mut self) -> Vec<&mut Value> {
+        let mut values = Vec::new();
+        match self {
+            Statement::Select(select) | Statement::Join(Join { select, .. }) => {
+                if let Some(filter) = &mut select.filter {
+                    filter.values_mut(&mut values);
+                }
+            }
+            Statement::Explain(statement) => values = statement.values_mut(),
+            Statement::Insert { rows, .. } => values.extend(rows.iter_mut().flatten()),
+            Statement::Update { assignments, filter, .. } => {
+                values.extend(assignments.iter_mut().map(|(_, value)| value));
+                if let Some(filter) = filter {
+                    filter.values_mut(&mut values);
+                }
+            }
+            Statement::Aggregate { filter: Some(filter), .. } | Statement::Delete { filter: Some(filter), .. } => {
+                filter.values_mut(&mut values)
+            }
+            _ => {}
+        }
+        values
+    }
+
+    // The table the statement reads or writes, None for the main table
+    fn table(&self) -> Option<&str> {
+        match self {
+            Statement::Select(select) => select.table.as_deref(),
+            Statement::Explain(statement) => statement.table(),
+            Statement::Insert { table, .. }
+            | Statement::Update { table, .. }
+            | Statement::Delete { table, .. }
+            | Statement::Aggregate { table, .. }
+            | Statement::CreateIndex { table, .. }
+            | Statement::DropIndex { table, .. } => table.as_deref(),
+            // Each side of a join names its own table
+            Statement::Join(_) | Statement::CreateTable { .. } | Statement::DropTable { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryOutput {
+    Rows { columns: Vec<String>, rows: Vec<Vec<Value>> },
+    // Number of records written by INSERT, UPDATE or DELETE
+    Changed { action: &'static str, count: usize },
+    // Outcome of a statement that changes the tables, e.g. "Created table users"
+    Done(String),
+    Explain(Explain),
+}
+
+impl QueryOutput {
+    // Short summary such as "Updated 3 records"
+    pub fn summary(&self) -> String {
+        match self {
+            QueryOutput::Rows { rows, .. } => format!("Retrieved {} records", rows.len()),
+            QueryOutput::Changed { action, count } => format!("{} {} records", action, count),
+            QueryOutput::Done(message) => message.clone(),
+            QueryOutput::Explain(explain) => format!("Explained {}", explain.access_path),
+        }
+    }
+}
+
+// A statement parsed once and run many times with different values for its
+// ? placeholders
+#[derive(Debug, Clone)]
+pub struct Prepared {
+    statement: Statement,
+    params: usize,
+}
+
+impl Prepared {
+    // Number of values each run needs
+    pub fn params(&self) -> usize {
+        self.params
+    }
+
+    // The statement with a value for each of its placeholders, ready to run
+    pub fn bind(&self, params: &[Value]) -> Result<Statement, QueryError> {
+        if params.len() != self.params {
+            return Err(QueryError::Invalid(format!(
+                "Expected {} parameters but got {}",
+                self.params,
+                params.len()
+            )));
+        }
+        if let Some(param) = params.iter().find(|p| matches!(p, Value::Param(_))) {
+            return Err(QueryError::Invalid(format!("{} is not a value", param)));
+        }
+
+        let mut statement = self.statement.clone();
+        for value in statement.values_mut() {
+            if let Value::Param(index) = value {
+                *value = params[*index].clone();
+            }
+        }
+        Ok(statement)
+    }
+}
+
+// Parse a statement with ? placeholders where values go, e.g.
+// "SELECT * FROM users WHERE age > ? LIMIT 10". Its table and columns are
+// checked now; the access path is chosen on each run, as it depends on the
+// values.
+pub fn prepare(db: &Database, query: &str) -> Result<Prepared, QueryError> {
+    let (statement, params) = parse_with_params(query)?;
+
+    let table = match statement.table() {
+        Some(name) => db
+            .table(name)
+            .ok_or_else(|| QueryError::Invalid(format!("Unknown table '{}'", name)))?,
+        None => db,
+    };
+    let layout = Layout { schema: table.schema() };
+    if let Some(filter) = statement.filter() {
+        check(layout, filter)?;
+    }
+
+    Ok(Prepared { statement, params })
+}
+
+// Parse and run a query against a database
+pub fn run(db: &mut Database, query: &str) -> Result<QueryOutput, QueryError> {
+    let statement = parse(query)?;
+    execute(db, &statement)
+}
+
+pub fn execute(db: &mut Database, statement: &Statement) -> Result<QueryOutput, QueryError> {
+    let db = match statement.table() {
+        Some(name) => db
+            .table_mut(name)
+            .ok_or_else(|| QueryError::Invalid(format!("Unknown table '{}'", name)))?,
+        None => db,
+    };
+
+    let layout = Layout { schema: db.schema() };
+    match statement {
+        Statement::Select(select) => {
+            let rows = select_rows(db, layout, select)?;
+            output_rows(layout, &select.columns, rows)
+        }
+        // Without access to other databases only tables of this one can be joined
+        Statement::Join(statement) => join(db, |_| None, statement),
+        Statement::Explain(statement) => Ok(QueryOutput::Explain(explain(db, statement)?)),
+        Statement::Aggregate { aggregates, filter, .. } => Ok(QueryOutput::Rows {
+            columns: aggregates.iter().map(|a| a.name()).collect(),
+            rows: vec![aggregate(db, aggregates, filter.as_ref())?],
+        }),
+        Statement::Insert { columns, rows, upsert, ttl, .. } => {
+            let mode = if *upsert { WriteMode::Upsert } else { WriteMode::Insert };
+            let expires_at = match ttl {
+                Some(ttl) => Some(
+                    now()
+                        .checked_add(*ttl)
+                        .ok_or_else(|| QueryError::Invalid(format!("TTL {} is too large", ttl)))?,
+                ),
+                None => None,
+            };
+            let writes = rows
+                .iter()
+                .map(|row| insert_write(layout, columns.as_deref(), row, mode, expires_at))
+                .collect::<Result<Vec<_>, _>>()?;
+            let count = db.write_all(writes)?;
+            Ok(QueryOutput::Changed {
+                action: if *upsert { "Upserted" } else { "Inserted" },
+                count,
+            })
+        }
+        Statement::Update { assignments, filter, .. } => {
+            let assignments = assignments
+                .iter()
+                .map(|(name, value)| {
+                    let index = layout.index_of(&Column::Named(name.clone()))?;
+                    Ok((index.unwrap_or_default(), value))
+                })
+                .collect::<Result<Vec<_>, QueryError>>()?;
+
+            let mut writes = Vec::new();
+            for mut row in matching_rows(db, layout, filter.as_ref())? {
+                for (index, value) in &assignments {
+                    row.cells[*index] = (*value).clone();
+                }
+                writes.push(Write::Put {
+                    key: row.key,
+                    value: layout.encode(&row.cells)?,
+                    mode: WriteMode::Update,
+                    expires_at: None,
+                });
+            }
+            let count = db.write_all(writes)?;
+            Ok(QueryOutput::Changed {
+                action: "Updated",
+                count,
+            })
+        }
+        Statement::Delete { filter, .. } => {
+            let writes = matching_rows(db, layout, filter.as_ref())?
+                .into_iter()
+                .map(|row| Write::Delete { key: row.key })
+                .collect();
+            let count = db.write_all(writes)?;
+            Ok(QueryOutput::Changed {
+                action: "Deleted",
+                count,
+            })
+        }
+        Statement::CreateTable { name, schema } => {
+            db.create_table(name, schema.clone())?;
+            Ok(QueryOutput::Done(format!("Created table {}", name)))
+        }
+        Statement::DropTable { name } => {
+            db.drop_table(name)?;
+            Ok(QueryOutput::Done(format!("Dropped table {}", name)))
+        }
+        Statement::CreateIndex { column, .. } => {
+            db.create_index(column)?;
+            Ok(QueryOutput::Done(format!("Created index on {}", column)))
+        }
+        Statement::DropIndex { column, .. } => {
+            db.drop_index(column)?;
+            Ok(QueryOutput::Done(format!("Dropped index on {}", column)))
+        }
+    }
+}
+
+// Compute aggregates over the records matching the filter. When the filter
+// only limits the key, COUNT(*) and MIN/MAX of the key are read from the tree
+// without going through the records: MIN and MAX of the whole table follow the
+// leftmost and rightmost paths.
+pub fn aggregate(
+    db: &Database,
+    aggregates: &[Aggregate],
+    filter: Option<&Condition>,
+) -> Result<Vec<Value>, QueryError> {
+    let layout = Layout { schema: db.schema() };
+    if let Some(filter) = filter {
+        check(layout, filter)?;
+    }
+
+    let mut indexes = Vec::new();
+    for aggregate in aggregates {
+        let index = match &aggregate.column {
+            Some(column) => layout.index_of(column)?,
+            None => None,
+        };
+        let numeric = match (index, layout.schema) {
+            (None, _) => true,
+            (Some(index), Some(schema)) => {
+                matches!(schema.columns[index].ty, ColumnType::Int | ColumnType::Float)
+            }
+            (Some(_), None) => false,
+        };
+        if matches!(aggregate.function, AggregateFn::Sum | AggregateFn::Avg) && !numeric {
+            return Err(QueryError::Invalid(format!("{} needs a numeric column", aggregate.name())));
+        }
+        indexes.push(index);
+    }
+
+    let key_only = filter.is_none_or(limits_key_only);
+    let bounds = filter.map_or((Bound::Unbounded, Bound::Unbounded), key_bounds);
+    let unbounded = bounds == (Bound::Unbounded, Bound::Unbounded);
+
+    let mut rows = None;
+    let mut values = Vec::new();
+    for (aggregate, index) in aggregates.iter().zip(indexes) {
+        let from_tree = key_only && index.is_none();
+        let value = match aggregate.function {
+            AggregateFn::Count if from_tree => Value::Int(db.tree().count(bounds) as i64),
+            AggregateFn::Min if from_tree => {
+                let first = if unbounded {
+                    db.tree().first()
+                } else {
+                    db.tree().range(bounds).into_iter().next()
+                };
+                first.map_or(Value::Null, |r| Value::Int(r.key as i64))
+            }
+            AggregateFn::Max if from_tree => {
+                let last = if unbounded {
+                    db.tree().last()
+                } else {
+                    db.tree().range(bounds).pop()
+                };
+                last.map_or(Value::Null, |r| Value::Int(r.key as i64))
+            }
+            function => {
+                if rows.is_none() {
+                    rows = Some(matching_rows(db, layout, filter)?);
+                }
+                let rows = rows.as_deref().unwrap_or_default();
+                let cells: Vec<Value> = match &aggregate.column {
+                    Some(_) => rows
+                        .iter()
+                        .map(|row| row.get(index))
+                        .filter(|cell| *cell != Value::Null)
+                        .collect(),
+                    None => rows.iter().map(|row| Value::Int(row.key as i64)).collect(),
+                };
+                fold(function, cells)?
+            }
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+// Reduce the non-NULL cells of a column to one value. MIN, MAX, SUM and AVG
+// of no cells are NULL.
+fn fold(function: AggregateFn, cells: Vec<Value>) -> Result<Value, QueryError> {
+    match function {
+        AggregateFn::Count => Ok(Value::Int(cells.len() as i64)),
+        AggregateFn::Min => Ok(cells.into_iter().min_by(sort_order).unwrap_or(Value::Null)),
+        AggregateFn::Max => Ok(cells.into_iter().max_by(sort_order).unwrap_or(Value::Null)),
+        AggregateFn::Sum | AggregateFn::Avg => {
+            if cells.is_empty() {
+                return Ok(Value::Null);
+            }
+
+            let count = cells.len();
+            let mut int_sum: Option<i64> = Some(0);
+            let mut sum = 0.0;
+            for cell in &cells {
+                match cell {
+                    Value::Int(number) => {
+                        int_sum = int_sum.and_then(|total| total.checked_add(*number));
+                        sum += *number as f64;
+                    }
+                    Value::Float(number) => {
+                        int_sum = None;
+                        sum += number;
+                    }
+                    _ => {}
+                }
+            }
+
+            Ok(match (function, int_sum) {
+                (AggregateFn::Avg, _) => Value::Float(sum / count as f64),
+                (_, Some(total)) if cells.iter().all(|c| matches!(c, Value::Int(_))) => Value::Int(total),
+                _ => Value::Float(sum),
+            })
+        }
+    }
+}
+
+// Whether the condition is nothing but limits on the key, so that key_bounds
+// describes exactly the records it matches
+fn limits_key_only(condition: &Condition) -> bool {
+    let fits = |value: &Value| matches!(value, Value::Int(number) if i32::try_from(*number).is_ok());
+    match condition {
+        Condition::Compare { column: Column::Key, op, value } => *op != CompareOp::Ne && fits(value),
+        Condition::Between { column: Column::Key, low, high } => fits(low) && fits(high),
+        Condition::And(a, b) => limits_key_only(a) && limits_key_only(b),
+        _ => false,
+    }
+}
+
+// How the records a filter may match are read from the tree
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum AccessPath {
+    // A single key, searched from the root
+    PointLookup(i32),
+    // The keys between two bounds, visiting only the subtrees that hold them
+    RangeScan(Bound<i32>, Bound<i32>),
+    // The keys found in the index on a column, each searched from the root
+    IndexScan(String),
+    #[default]
+    FullScan,
+}
+
+impl fmt::Display for AccessPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessPath::PointLookup(key) => write!(f, "point lookup of key {}", key),
+            AccessPath::RangeScan(start, end) => {
+                write!(f, "range scan of keys ")?;
+                match start {
+                    Bound::Included(key) => write!(f, "[{}", key)?,
+                    Bound::Excluded(key) => write!(f, "({}", key)?,
+                    Bound::Unbounded => write!(f, "(-inf")?,
+                }
+                match end {
+                    Bound::Included(key) => write!(f, ", {}]", key),
+                    Bound::Excluded(key) => write!(f, ", {})", key),
+                    Bound::Unbounded => write!(f, ", inf)"),
+                }
+            }
+            AccessPath::IndexScan(column) => write!(f, "index scan on {}", column),
+            AccessPath::FullScan => write!(f, "full scan"),
+        }
+    }
+}
+
+// The access path taken to find a filter's candidates and the work it took
+#[derive(Default)]
+struct Scan {
+    path: AccessPath,
+    // Nodes of the record tree visited
+    tree: Trace,
+    // Nodes of the index visited, for index scans
+    index: Option<Trace>,
+}
+
+// Build the write for one row of an INSERT. Columns left out are NULL.
+fn insert_write(
+    layout: Layout,
+    columns: Option<&[Column]>,
+    row: &[Value],
+    mode: WriteMode,
+    expires_at: Option<u64>,
+) -> Result<Write, QueryError> {
+    let names = layout.names();
+    let mut key = None;
+    let mut cells = vec![Value::Null; names.len()];
+
+    match columns {
+        Some(columns) => {
+            if row.len() != columns.len() {
+                return Err(QueryError::Invalid(format!(
+                    "Expected {} values but got {}",
+                    columns.len(),
+                    row.len()
+                )));
+            }
+            for (column, value) in columns.iter().zip(row) {
+                match layout.index_of(column)? {
+                    Some(index) => cells[index] = value.clone(),
+                    None => key = Some(value),
+                }
+            }
+        }
+        None => {
+            if row.len() != names.len() + 1 {
+                return Err(QueryError::Invalid(format!(
+                    "Expected {} values (key, {}) but got {}",
+                    names.len() + 1,
+                    names.join(", "),
+                    row.len()
+                )));
+            }
+            key = row.first();
+            cells.clone_from_slice(&row[1..]);
+        }
+    }
+
+    let key = match key {
+        Some(Value::Int(key)) => {
+            i32::try_from(*key).map_err(|_| QueryError::Invalid(format!("Key {} is out of range", key)))?
+        }
+        Some(other) => return Err(QueryError::Invalid(format!("Key {} is not an integer", other))),
+        None => return Err(QueryError::Invalid("Every row needs a key".to_string())),
+    };
+
+    Ok(Write::Put {
+        key,
+        value: layout.encode(&cells)?,
+        mode,
+        expires_at,
+    })
+}
+
+// The columns of the rows asked for, every column for SELECT *
+fn output_rows(layout: Layout, columns: &[Column], rows: Vec<Row>) -> Result<QueryOutput, QueryError> {
+    let columns = if columns.is_empty() {
+        let mut columns = vec![Column::Key];
+        columns.extend(layout.names().into_iter().map(Column::Named));
+        columns
+    } else {
+        columns.to_vec()
+    };
+    let indexes = columns
+        .iter()
+        .map(|c| layout.index_of(c))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(QueryOutput::Rows {
+        columns: columns.iter().map(|c| c.name().to_string()).collect(),
+        rows: rows
+            .iter()
+            .map(|row| indexes.iter().map(|&i| row.get(i)).collect())
+            .collect(),
+    })
+}
+
+fn select_rows(db: &Database, layout: Layout, select: &Select) -> Result<Vec<Row>, QueryError> {
+    let rows = matching_rows(db, layout, select.filter.as_ref())?;
+    order_and_limit(layout, select, rows)
+}
+
+// Apply the ORDER BY, LIMIT and OFFSET of a select to rows in key order
+fn order_and_limit(layout: Layout, select: &Select, mut rows: Vec<Row>) -> Result<Vec<Row>, QueryError> {
+    if let Some(order_by) = &select.order_by {
+        // Records come out of the tree in key order already
+        if let Some(index) = layout.index_of(&order_by.column)? {
+            rows.sort_by(|a, b| sort_order(&a.cells[index], &b.cells[index]));
+        }
+        if order_by.descending {
+            rows.reverse();
+        }
+    }
+
+    let limit = select.limit.unwrap_or(usize::MAX);
+    Ok(rows.into_iter().skip(select.offset).take(limit).collect())
+}
+
+// Run a join, finding the databases its tables name with `databases`
+pub fn join<'a>(
+    db: &'a Database,
+    databases: impl Fn(&str) -> Option<&'a Database>,
+    join: &Join,
+) -> Result<QueryOutput, QueryError> {
+    if join.left.label() == join.right.label() {
+        return Err(QueryError::Invalid(format!(
+            "Both tables of the join are called '{}'",
+            join.left.label()
+        )));
+    }
+    let table = |source: &Source| {
+        let db = match &source.database {
+            Some(name) => databases(name).ok_or_else(|| QueryError::Invalid(format!("Unknown database '{}'", name)))?,
+            None => db,
+        };
+        db.table(&source.table)
+            .ok_or_else(|| QueryError::Invalid(format!("Unknown table '{}'", source.table)))
+    };
+    let (left, right) = (table(&join.left)?, table(&join.right)?);
+
+    // A joined row holds the cells of the left table, then those of the right
+    // one, named after their table
+    let mut columns = Vec::new();
+    for (source, db) in [(&join.left, left), (&join.right, right)] {
+        let layout = Layout { schema: db.schema() };
+        let types = match db.schema() {
+            Some(schema) => schema.columns.iter().map(|c| c.ty.clone()).collect(),
+            None => vec![ColumnType::Text(None)],
+        };
+        for (name, ty) in layout.names().into_iter().zip(types) {
+            columns.push(ColumnDef {
+                name: format!("{}.{}", source.label(), name),
+                ty,
+                nullable: true,
+            });
+        }
+    }
+    let schema = Schema::new(columns).map_err(QueryError::Invalid)?;
+    let layout = Layout { schema: Some(&schema) };
+
+    let mut select = join.select.clone();
+    let mut names: Vec<&mut Column> = select.columns.iter_mut().collect();
+    if let Some(filter) = &mut select.filter {
+        filter.columns_mut(&mut names);
+    }
+    if let Some(order_by) = &mut select.order_by {
+        names.push(&mut order_by.column);
+    }
+    for column in names {
+        qualify(&schema, column)?;
+    }
+
+    let bounds = match &select.filter {
+        Some(filter) => {
+            check(layout, filter)?;
+            key_bounds(filter)
+        }
+        None => (Bound::Unbounded, Bound::Unbounded),
+    };
+    let mut rows = Vec::new();
+    for row in merge_join(left, right, join.kind, bounds)? {
+        if select.filter.as_ref().is_none_or(|filter| matches(layout, filter, &row)) {
+            rows.push(row);
+        }
+    }
+
+    let rows = order_and_limit(layout, &select, rows)?;
+    output_rows(layout, &select.columns, rows)
+}
+
+// Name a column of a join after its table when only one of the tables has it
+fn qualify(schema: &Schema, column: &mut Column) -> Result<(), QueryError> {
+    let name = match column {
+        Column::Named(name) if !name.contains('.') => name,
+        _ => return Ok(()),
+    };
+    let suffix = format!(".{}", name);
+    let found: Vec<String> = schema.names().into_iter().filter(|c| c.ends_with(&suffix)).collect();
+    match found.as_slice() {
+        [qualified] => *name = qualified.clone(),
+        [] => {}
+        _ => {
+            return Err(QueryError::Invalid(format!(
+                "Column '{}' is in both tables, name it as {}",
+                name,
+                found.join(" or ")
+            )))
+        }
+    }
+    Ok(())
+}
+
+// Pair the records of two tables with keys in the bounds. Both come out of
+// their trees in key order, so one pass over each finds every pair.
+fn merge_join(
+    left: &Database,
+    right: &Database,
+    kind: JoinKind,
+    bounds: (Bound<i32>, Bound<i32>),
+) -> Result<Vec<Row>, QueryError> {
+    let left_layout = Layout { schema: left.schema() };
+    let right_layout = Layout { schema: right.schema() };
+    let missing = vec![Value::Null; right_layout.names().len()];
+
+    let mut right_records = right.tree().range(bounds).into_iter().peekable();
+    let mut rows = Vec::new();
+    for record in left.tree().range(bounds) {
+        while right_records.next_if(|other| other.key < record.key).is_some() {}
+        let cells = match right_records.next_if(|other| other.key == record.key) {
+            Some(other) => right_layout.row(other)?.cells,
+            None if kind == JoinKind::Left => missing.clone(),
+            None => continue,
+        };
+
+        let mut row = left_layout.row(record)?;
+        row.cells.extend(cells);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// Records matching the condition, in key order
+pub fn find_records(db: &Database, filter: &Condition) -> Result<Vec<Record>, QueryError> {
+    let layout = Layout { schema: db.schema() };
+    check(layout, filter)?;
+
+    let mut records = Vec::new();
+    for record in candidates(db, filter, &mut Scan::default()) {
+        if matches(layout, filter, &layout.row(record.clone())?) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+// Rows matching the condition, in key order
+fn matching_rows(db: &Database, layout: Layout, filter: Option<&Condition>) -> Result<Vec<Row>, QueryError> {
+    let (records, filter) = match filter {
+        Some(filter) => {
+            check(layout, filter)?;
+            (candidates(db, filter, &mut Scan::default()), Some(filter))
+        }
+        None => (db.tree().get_all_records(), None),
+    };
+
+    let mut rows = Vec::new();
+    for record in records {
+        let row = layout.row(record)?;
+        match filter {
+            Some(filter) if !matches(layout, filter, &row) => {}
+            _ => rows.push(row),
+        }
+    }
+    Ok(rows)
+}
+
+// Records that may match the condition, in key order. Conditions on the key
+// narrow the part of the tree that is scanned. Otherwise an index on a column
+// the condition compares picks the records, and only without one is every
+// record read.
+fn candidates(db: &Database, filter: &Condition, scan: &mut Scan) -> Vec<Record> {
+    let bounds = key_bounds(filter);
+    if let (Bound::Included(start), Bound::Included(end)) = bounds {
+        if start == end {
+            scan.path = AccessPath::PointLookup(start);
+            return db
+                .tree()
+                .search_traced(start, &mut scan.tree)
+                .map(|value| Record { key: start, value })
+                .into_iter()
+                .collect();
+        }
+    }
+    if bounds != (Bound::Unbounded, Bound::Unbounded) {
+        scan.path = AccessPath::RangeScan(bounds.0, bounds.1);
+        return db.tree().range_traced(bounds, &mut scan.tree);
+    }
+
+    let mut index_trace = Trace::default();
+    match index_lookup(db, filter, &mut index_trace) {
+        Some((column, mut keys)) => {
+            scan.path = AccessPath::IndexScan(column);
+            scan.index = Some(index_trace);
+            keys.sort();
+            keys.into_iter()
+                .filter_map(|key| {
+                    let value = db.tree().search_traced(key, &mut scan.tree)?;
+                    Some(Record { key, value })
+                })
+                .collect()
+        }
+        None => {
+            scan.path = AccessPath::FullScan;
+            db.tree().range_traced(.., &mut scan.tree)
+        }
+    }
+}
+
+// Keys an index finds for the condition, or None if no index helps with it
+fn index_lookup(db: &Database, condition: &Condition, trace: &mut Trace) -> Option<(String, Vec<i32>)> {
+    match condition {
+        Condition::Compare {
+            column: Column::Named(name),
+            op,
+            value,
+        } => {
+            let index = db.index(name)?;
+            let value = index_literal(db.schema(), name, value)?;
+            let (start, end) = match op {
+                CompareOp::Eq => (Bound::Included(value.clone()), Bound::Included(value)),
+                CompareOp::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+                CompareOp::Le => (Bound::Unbounded, Bound::Included(value)),
+                CompareOp::Gt => (Bound::Excluded(value), Bound::Unbounded),
+                CompareOp::Ge => (Bound::Included(value), Bound::Unbounded),
+                CompareOp::Ne => return None,
+            };
+            Some((name.clone(), index.lookup(start, end, trace)))
+        }
+        Condition::Between {
+            column: Column::Named(name),
+            low,
+            high,
+        } => {
+            let index = db.index(name)?;
+            let low = index_literal(db.schema(), name, low)?;
+            let high = index_literal(db.schema(), name, high)?;
+            Some((name.clone(), index.lookup(Bound::Included(low), Bound::Included(high), trace)))
+        }
+        Condition::And(a, b) => index_lookup(db, a, trace).or_else(|| index_lookup(db, b, trace)),
+        _ => None,
+    }
+}
+
+// A literal as the index on a column holds it. None when the literal is of
+// another type, since compare() would then not follow the index order.
+fn index_literal(schema: Option<&Schema>, column: &str, literal: &Value) -> Option<IndexKey> {
+    let ty = match schema {
+        Some(schema) => schema.columns[schema.index_of(column)?].ty.clone(),
+        None => ColumnType::Text(None),
+    };
+
+    match (ty, literal) {
+        (ColumnType::Int, Value::Int(_))
+        | (ColumnType::Float, Value::Float(_))
+        | (ColumnType::Text(_), Value::Text(_))
+        | (ColumnType::Bool, Value::Bool(_)) => Some(IndexKey::from(literal)),
+        (ColumnType::Float, Value::Int(number)) => Some(IndexKey::Float(*number as f64)),
+        _ => None,
+    }
+}
+
+// Reject conditions that can never be evaluated
+fn check(layout: Layout, condition: &Condition) -> Result<(), QueryError> {
+    match condition {
+        Condition::Compare { column: Column::Key, value: Value::Text(_), .. }
+        | Condition::Between { column: Column::Key, low: Value::Text(_), .. }
+        | Condition::Between { column: Column::Key, high: Value::Text(_), .. } => Err(
+            QueryError::Invalid("The key can only be compared with integers".to_string()),
+        ),
+        Condition::Compare { column, .. }
+        | Condition::Between { column, .. }
+        | Condition::Like { column, .. }
+        | Condition::IsNull { column } => layout.index_of(column).map(|_| ()),
+        Condition::And(a, b) | Condition::Or(a, b) => {
+            check(layout, a)?;
+            check(layout, b)
+        }
+        Condition::Not(a) => check(layout, a),
+    }
+}
+
+// Smallest key range that contains every record the condition can match
+fn key_bounds(condition: &Condition) -> (Bound<i32>, Bound<i32>) {
+    match condition {
+        Condition::Compare {
+            column: Column::Key,
+            op,
+            value: Value::Int(number),
+        } => match i32::try_from(*number) {
+            Ok(key) => match op {
+                CompareOp::Eq => (Bound::Included(key), Bound::Included(key)),
+                CompareOp::Lt => (Bound::Unbounded, Bound::Excluded(key)),
+                CompareOp::Le => (Bound::Unbounded, Bound::Included(key)),
+                CompareOp::Gt => (Bound::Excluded(key), Bound::Unbounded),
+                CompareOp::Ge => (Bound::Included(key), Bound::Unbounded),
+                CompareOp::Ne => (Bound::Unbounded, Bound::Unbounded),
+            },
+            Err(_) => (Bound::Unbounded, Bound::Unbounded),
+        },
+        Condition::Between {
+            column: Column::Key,
+            low: Value::Int(low),
+            high: Value::Int(high),
+        } => match (i32::try_from(*low), i32::try_from(*high)) {
+            (Ok(low), Ok(high)) => (Bound::Included(low), Bound::Included(high)),
+            _ => (Bound::Unbounded, Bound::Unbounded),
+        },
+        Condition::And(a, b) => {
+            let (a_start, a_end) = key_bounds(a);
+            let (b_start, b_end) = key_bounds(b);
+            (tighter_start(a_start, b_start), tighter_end(a_end, b_end))
+        }
+        _ => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+fn tighter_start(a: Bound<i32>, b: Bound<i32>) -> Bound<i32> {
+    match (a, b) {
+        (Bound::Unbounded, other) | (other, Bound::Unbounded) => other,
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.max(y)),
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.max(y)),
+        (Bound::Included(i), Bound::Excluded(e)) | (Bound::Excluded(e), Bound::Included(i)) => {
+            if i > e {
+                Bound::Included(i)
+            } else {
+                Bound::Excluded(e)
+            }
+        }
+    }
+}
+
+fn tighter_end(a: Bound<i32>, b: Bound<i32>) -> Bound<i32> {
+    match (a, b) {
+        (Bound::Unbounded, other) | (other, Bound::Unbounded) => other,
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.min(y)),
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.min(y)),
+        (Bound::Included(i), Bound::Excluded(e)) | (Bound::Excluded(e), Bound::Included(i)) => {
+            if i < e {
+                Bound::Included(i)
+            } else {
+                Bound::Excluded(e)
+            }
+        }
+    }
+}
+
+fn matches(layout: Layout, condition: &Condition, row: &Row) -> bool {
+    // Columns were resolved by check() already
+    let cell = |column: &Column| row.get(layout.index_of(column).ok().flatten());
+    match condition {
+        Condition::Compare { column, op, value } => match compare(&cell(column), value) {
+            Some(ordering) => match op {
+                CompareOp::Eq => ordering.is_eq(),
+                CompareOp::Ne => ordering.is_ne(),
+                CompareOp::Lt => ordering.is_lt(),
+                CompareOp::Le => ordering.is_le(),
+                CompareOp::Gt => ordering.is_gt(),
+                CompareOp::Ge => ordering.is_ge(),
+            },
+            None => false,
+        },
+        Condition::Between { column, low, high } => {
+            let cell = cell(column);
+            compare(&cell, low).is_some_and(|o| o.is_ge()) && compare(&cell, high).is_some_and(|o| o.is_le())
+        }
+        Condition::Like { column, pattern } => match cell(column) {
+            Value::Null => false,
+            cell => {
+                let text: Vec<char> = cell.to_string().chars().collect();
+                let pattern: Vec<char> = pattern.chars().collect();
+                like(&text, &pattern)
+            }
+        },
+        Condition::IsNull { column } => cell(column) == Value::Null,
+        Condition::And(a, b) => matches(layout, a, row) && matches(layout, b, row),
+        Condition::Or(a, b) => matches(layout, a, row) || matches(layout, b, row),
+        Condition::Not(a) => !matches(layout, a, row),
+    }
+}
+
+// Numbers compare numerically, anything compared with text compares as text.
+// Comparisons with NULL have no result, so they never match.
+fn compare(cell: &Value, literal: &Value) -> Option<Ordering> {
+    match (cell, literal) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (a, b) => Some(a.to_string().cmp(&b.to_string())),
+    }
+}
+
+// ORDER BY puts NULLs first
+fn sort_order(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (a, b) => compare(a, b).unwrap_or(Ordering::Equal),
+    }
+}
+
+// SQL LIKE matching with % and _ wildcards
+fn like(text: &[char], pattern: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('%', rest)) => (0..=text.len()).any(|i| like(&text[i..], rest)),
+        Some(('_', rest)) => !text.is_empty() && like(&text[1..], rest),
+        Some((c, rest)) => text.first() == Some(c) && like(&text[1..], rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_ttl_past_the_end_of_time_is_refused() {
+        let dir = std::env::temp_dir().join(format!("ryou-query-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut db = Database::open(&dir.join("ttl.db").to_string_lossy(), false).unwrap();
+
+        let error = run(&mut db, &format!("INSERT VALUES (1, 'one') TTL {}", u64::MAX)).unwrap_err();
+        assert_eq!(error.to_string(), format!("Syntax error: Number out of range: {}", u64::MAX));
+
+        // A statement built by other means than parsing
+        let insert = Statement::Insert {
+            table: None,
+            columns: None,
+            rows: vec![vec![Value::Int(1), Value::Text("one".to_string())]],
+            upsert: false,
+            ttl: Some(u64::MAX),
+        };
+        let error = execute(&mut db, &insert).unwrap_err();
+        assert_eq!(error.to_string(), format!("TTL {} is too large", u64::MAX));
+        assert_eq!(db.tree().len(), 0);
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,823 @@
+use super::{
+    Aggregate, AggregateFn, Column, CompareOp, Condition, Join, JoinKind, OrderBy, QueryError, Select, Source,
+    Statement, Value,
+};
+use crate::db::MAIN_TABLE;
+use crate::schema::{ColumnDef, ColumnType, Schema};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Symbol(&'static str),
+    // A ? placeholder
+    Param,
+}
+
+// Words that can't be used as column names
+const KEYWORDS: [&str; 35] = [
+    "select", "insert", "update", "delete", "values", "set", "where", "and", "or", "not",
+    "between", "like", "is", "null", "true", "false", "order", "by", "asc", "desc", "limit",
+    "offset", "from", "into", "create", "drop", "table", "index", "on", "explain", "upsert",
+    "join", "inner", "left", "outer",
+];
+
+const SYMBOLS: [&str; 12] = ["<=", ">=", "!=", "<>", "=", "<", ">", "(", ")", ",", "*", "."];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() || c == ';' {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            // Quoted string, the quote is escaped by doubling it
+            let quote = c;
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some(&ch) if ch == quote && chars.get(i + 1) == Some(&quote) => {
+                        text.push(quote);
+                        i += 2;
+                    }
+                    Some(&ch) if ch == quote => {
+                        i += 1;
+                        break;
+                    }
+                    Some(&ch) => {
+                        text.push(ch);
+                        i += 1;
+                    }
+                    None => return Err(QueryError::Syntax("Unterminated string".to_string())),
+                }
+            }
+            tokens.push(Token::Str(text));
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let is_float = chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit());
+            if is_float {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+
+            let text: String = chars[start..i].iter().collect();
+            if is_float {
+                let number = text
+                    .parse::<f64>()
+                    .map_err(|_| QueryError::Syntax(format!("Invalid number: {}", text)))?;
+                tokens.push(Token::Float(number));
+            } else {
+                let number = text
+                    .parse::<i64>()
+                    .map_err(|_| QueryError::Syntax(format!("Number out of range: {}", text)))?;
+                tokens.push(Token::Int(number));
+            }
+        } else if c == '?' {
+            tokens.push(Token::Param);
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                Some(symbol) => {
+                    tokens.push(Token::Symbol(symbol));
+                    i += symbol.len();
+                }
+                None => return Err(QueryError::Syntax(format!("Unexpected character '{}'", c))),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Number of ? placeholders seen so far, None where they are not allowed
+    params: Option<usize>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&keyword.to_uppercase()))
+        }
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek_symbol(symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), QueryError> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", symbol)))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> QueryError {
+        match self.peek() {
+            Some(token) => QueryError::Syntax(format!("Expected {} but found {}", expected, describe(token))),
+            None => QueryError::Syntax(format!("Expected {} but the query ended", expected)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, QueryError> {
+        if self.eat_keyword("explain") {
+            return Ok(Statement::Explain(Box::new(self.statement()?)));
+        }
+
+        let statement = if self.eat_keyword("select") {
+            if self.aggregate_ahead() {
+                self.aggregate()?
+            } else {
+                self.select()?
+            }
+        } else if self.eat_keyword("insert") {
+            self.insert(false)?
+        } else if self.eat_keyword("upsert") {
+            self.insert(true)?
+        } else if self.eat_keyword("update") {
+            self.update()?
+        } else if self.eat_keyword("delete") {
+            let table = self.table_clause("from")?;
+            Statement::Delete {
+                table,
+                filter: self.filter()?,
+            }
+        } else if self.eat_keyword("create") {
+            if self.eat_keyword("index") {
+                let (table, column) = self.index_target()?;
+                Statement::CreateIndex { table, column }
+            } else {
+                self.create_table()?
+            }
+        } else if self.eat_keyword("drop") {
+            if self.eat_keyword("index") {
+                let (table, column) = self.index_target()?;
+                Statement::DropIndex { table, column }
+            } else {
+                self.expect_keyword("table")?;
+                Statement::DropTable {
+                    name: self.table_name()?,
+                }
+            }
+        } else {
+            return Err(self.unexpected("SELECT, INSERT, UPSERT, UPDATE, DELETE, CREATE, DROP or EXPLAIN"));
+        };
+
+        self.end()?;
+        Ok(statement)
+    }
+
+    // SELECT <* | column, ...> [FROM table [[INNER | LEFT [OUTER]] JOIN table]] [WHERE ...]
+    // [ORDER BY column [ASC|DESC]] [LIMIT n [OFFSET m]]
+    fn select(&mut self) -> Result<Statement, QueryError> {
+        let mut columns = Vec::new();
+        if !self.eat_symbol("*") {
+            loop {
+                columns.push(self.column()?);
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        let from = if self.eat_keyword("from") {
+            Some(self.source()?)
+        } else {
+            None
+        };
+        let join = self.join_clause()?;
+        let filter = self.filter()?;
+
+        let mut order_by = None;
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            let column = self.column()?;
+            let descending = if self.eat_keyword("desc") {
+                true
+            } else {
+                self.eat_keyword("asc");
+                false
+            };
+            order_by = Some(OrderBy { column, descending });
+        }
+
+        let mut limit = None;
+        let mut offset = 0;
+        if self.eat_keyword("limit") {
+            limit = Some(self.count()?);
+            if self.eat_keyword("offset") {
+                offset = self.count()?;
+            }
+        }
+
+        let mut select = Select {
+            table: None,
+            columns,
+            filter,
+            order_by,
+            limit,
+            offset,
+        };
+        match (from, join) {
+            (from, Some((kind, right))) => Ok(Statement::Join(Join {
+                kind,
+                left: from.unwrap_or(Source {
+                    database: None,
+                    table: MAIN_TABLE.to_string(),
+                }),
+                right,
+                select,
+            })),
+            (Some(Source { database: Some(_), .. }), None) => Err(QueryError::Invalid(
+                "Tables of other databases can only be read in a JOIN".to_string(),
+            )),
+            (from, None) => {
+                select.table = from.map(|source| source.table);
+                Ok(Statement::Select(select))
+            }
+        }
+    }
+
+    // [INNER | LEFT [OUTER]] JOIN table
+    fn join_clause(&mut self) -> Result<Option<(JoinKind, Source)>, QueryError> {
+        let kind = if self.eat_keyword("left") {
+            self.eat_keyword("outer");
+            JoinKind::Left
+        } else if self.eat_keyword("inner") || self.peek_keyword("join") {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        self.expect_keyword("join")?;
+        Ok(Some((kind, self.source()?)))
+    }
+
+    // table or database.table, where "main" is the records of the database
+    // itself
+    fn source(&mut self) -> Result<Source, QueryError> {
+        let name = self.table_name()?;
+        if self.eat_symbol(".") {
+            Ok(Source {
+                database: Some(name),
+                table: self.table_name()?,
+            })
+        } else {
+            Ok(Source {
+                database: None,
+                table: name,
+            })
+        }
+    }
+
+    // Whether the select list starts with a call such as COUNT(
+    fn aggregate_ahead(&self) -> bool {
+        let is_function = match self.peek() {
+            Some(Token::Word(word)) => aggregate_fn(word).is_some(),
+            _ => false,
+        };
+        is_function && matches!(self.tokens.get(self.pos + 1), Some(Token::Symbol("(")))
+    }
+
+    // SELECT COUNT(* | column) | MIN(column) | MAX(column) | SUM(column) | AVG(column), ...
+    // [FROM table] [WHERE ...]
+    fn aggregate(&mut self) -> Result<Statement, QueryError> {
+        let mut aggregates = Vec::new();
+        loop {
+            let function = match self.next() {
+                Some(Token::Word(word)) => aggregate_fn(&word),
+                _ => None,
+            };
+            let function = match function {
+                Some(function) => function,
+                None => {
+                    self.pos -= 1;
+                    return Err(self.unexpected("COUNT, MIN, MAX, SUM or AVG"));
+                }
+            };
+
+            self.expect_symbol("(")?;
+            let column = if function == AggregateFn::Count && self.eat_symbol("*") {
+                None
+            } else {
+                Some(self.column()?)
+            };
+            self.expect_symbol(")")?;
+            aggregates.push(Aggregate { function, column });
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        let table = self.table_clause("from")?;
+        Ok(Statement::Aggregate {
+            table,
+            aggregates,
+            filter: self.filter()?,
+        })
+    }
+
+    // INSERT [INTO table] [(column, ...)] VALUES (key, cell, ...)[, (key, cell, ...) ...] [TTL secs]
+    // Without a column list the key comes first, then the columns in order.
+    // UPSERT takes the same form.
+    fn insert(&mut self, upsert: bool) -> Result<Statement, QueryError> {
+        let table = self.table_clause("into")?;
+        let mut columns = None;
+        if self.eat_symbol("(") {
+            let mut names = Vec::new();
+            loop {
+                names.push(self.column()?);
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+            self.expect_symbol(")")?;
+            columns = Some(names);
+        }
+
+        self.expect_keyword("values")?;
+
+        let mut rows = Vec::new();
+        loop {
+            self.expect_symbol("(")?;
+            let mut row = Vec::new();
+            loop {
+                row.push(self.literal()?);
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+            self.expect_symbol(")")?;
+            rows.push(row);
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        let ttl = if self.eat_keyword("ttl") {
+            Some(self.count()? as u64)
+        } else {
+            None
+        };
+
+        Ok(Statement::Insert {
+            table,
+            columns,
+            rows,
+            upsert,
+            ttl,
+        })
+    }
+
+    // UPDATE [table] SET column = <literal>[, column = <literal> ...] [WHERE ...]
+    fn update(&mut self) -> Result<Statement, QueryError> {
+        let table = if self.peek_keyword("set") {
+            None
+        } else {
+            Some(self.table_name()?)
+        };
+        self.expect_keyword("set")?;
+
+        let mut assignments = Vec::new();
+        loop {
+            let name = match self.column()? {
+                Column::Named(name) => name,
+                Column::Key => return Err(QueryError::Invalid("The key of a record cannot be updated".to_string())),
+            };
+            self.expect_symbol("=")?;
+            assignments.push((name, self.literal()?));
+
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        Ok(Statement::Update {
+            table,
+            assignments,
+            filter: self.filter()?,
+        })
+    }
+
+    // CREATE TABLE name [(column TYPE, ...)]
+    fn create_table(&mut self) -> Result<Statement, QueryError> {
+        self.expect_keyword("table")?;
+        let name = self.table_name()?;
+
+        let mut schema = None;
+        if self.eat_symbol("(") {
+            let columns = self.column_defs()?;
+            self.expect_symbol(")")?;
+            schema = Some(Schema::new(columns).map_err(QueryError::Invalid)?);
+        }
+
+        Ok(Statement::CreateTable { name, schema })
+    }
+
+    // ON [table] (column), after CREATE INDEX or DROP INDEX
+    fn index_target(&mut self) -> Result<(Option<String>, String), QueryError> {
+        self.expect_keyword("on")?;
+        let table = if self.peek_symbol("(") {
+            None
+        } else {
+            Some(self.table_name()?)
+        };
+
+        self.expect_symbol("(")?;
+        let column = match self.column()? {
+            Column::Named(name) => name,
+            Column::Key => return Err(QueryError::Invalid("The key is indexed already".to_string())),
+        };
+        self.expect_symbol(")")?;
+        Ok((table, column))
+    }
+
+    // Optional "<keyword> table" naming the table a statement works on
+    fn table_clause(&mut self, keyword: &str) -> Result<Option<String>, QueryError> {
+        if self.eat_keyword(keyword) {
+            Ok(Some(self.table_name()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn table_name(&mut self) -> Result<String, QueryError> {
+        match self.next() {
+            Some(Token::Word(word)) if !is_keyword(&word) => Ok(word),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("a table name"))
+            }
+        }
+    }
+
+    fn filter(&mut self) -> Result<Option<Condition>, QueryError> {
+        if self.eat_keyword("where") {
+            Ok(Some(self.or()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition, QueryError> {
+        let mut condition = self.and()?;
+        while self.eat_keyword("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, QueryError> {
+        let mut condition = self.not()?;
+        while self.eat_keyword("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.not()?));
+        }
+        Ok(condition)
+    }
+
+    fn not(&mut self) -> Result<Condition, QueryError> {
+        if self.eat_keyword("not") {
+            Ok(Condition::Not(Box::new(self.not()?)))
+        } else {
+            self.predicate()
+        }
+    }
+
+    // column <op> literal, column [NOT] BETWEEN a AND b, column [NOT] LIKE 'pattern'
+    // or a parenthesised condition
+    fn predicate(&mut self) -> Result<Condition, QueryError> {
+        if self.eat_symbol("(") {
+            let condition = self.or()?;
+            self.expect_symbol(")")?;
+            return Ok(condition);
+        }
+
+        let column = self.column()?;
+
+        if self.eat_keyword("is") {
+            let negated = self.eat_keyword("not");
+            self.expect_keyword("null")?;
+            return Ok(negate(Condition::IsNull { column }, negated));
+        }
+
+        let negated = self.eat_keyword("not");
+
+        if self.eat_keyword("between") {
+            let low = self.literal()?;
+            self.expect_keyword("and")?;
+            let high = self.literal()?;
+            let condition = Condition::Between { column, low, high };
+            return Ok(negate(condition, negated));
+        }
+
+        if self.eat_keyword("like") {
+            let pattern = match self.next() {
+                Some(Token::Str(pattern)) => pattern,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected("a quoted pattern"));
+                }
+            };
+            return Ok(negate(Condition::Like { column, pattern }, negated));
+        }
+
+        if negated {
+            return Err(self.unexpected("BETWEEN or LIKE"));
+        }
+
+        let op = match self.next() {
+            Some(Token::Symbol("=")) => CompareOp::Eq,
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => CompareOp::Ne,
+            Some(Token::Symbol("<")) => CompareOp::Lt,
+            Some(Token::Symbol("<=")) => CompareOp::Le,
+            Some(Token::Symbol(">")) => CompareOp::Gt,
+            Some(Token::Symbol(">=")) => CompareOp::Ge,
+            _ => {
+                self.pos -= 1;
+                return Err(self.unexpected("a comparison, BETWEEN or LIKE"));
+            }
+        };
+        let value = self.literal()?;
+
+        Ok(Condition::Compare { column, op, value })
+    }
+
+    // column, or table.column in a join. Both tables of a join share the key.
+    fn column(&mut self) -> Result<Column, QueryError> {
+        let column = match self.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("key") => Column::Key,
+            Some(Token::Word(word)) if !is_keyword(&word) => Column::Named(word),
+            _ => {
+                self.pos -= 1;
+                return Err(self.unexpected("a column name"));
+            }
+        };
+
+        match column {
+            Column::Named(table) if self.eat_symbol(".") => match self.column()? {
+                Column::Named(name) => Ok(Column::Named(format!("{}.{}", table, name))),
+                Column::Key => Ok(Column::Key),
+            },
+            column => Ok(column),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, QueryError> {
+        match self.next() {
+            Some(Token::Int(number)) => Ok(Value::Int(number)),
+            Some(Token::Float(number)) => Ok(Value::Float(number)),
+            Some(Token::Str(text)) => Ok(Value::Text(text)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("true") => Ok(Value::Bool(true)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("false") => Ok(Value::Bool(false)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("null") => Ok(Value::Null),
+            Some(Token::Param) => match &mut self.params {
+                Some(params) => {
+                    *params += 1;
+                    Ok(Value::Param(*params - 1))
+                }
+                None => Err(QueryError::Syntax(
+                    "? placeholders can only be used in prepared statements".to_string(),
+                )),
+            },
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("a number, quoted string, TRUE, FALSE or NULL"))
+            }
+        }
+    }
+
+    // name TYPE[(max length)] [NULL | NOT NULL], ...
+    fn column_defs(&mut self) -> Result<Vec<ColumnDef>, QueryError> {
+        let mut columns = Vec::new();
+        loop {
+            let name = match self.column()? {
+                Column::Named(name) if name.contains('.') => {
+                    return Err(QueryError::Invalid(format!("Column name '{}' cannot contain '.'", name)))
+                }
+                Column::Named(name) => name,
+                Column::Key => return Err(QueryError::Invalid("'key' is reserved for the record key".to_string())),
+            };
+
+            let ty = match self.next() {
+                Some(Token::Word(word)) => match word.to_ascii_lowercase().as_str() {
+                    "int" | "integer" => ColumnType::Int,
+                    "float" | "real" | "double" => ColumnType::Float,
+                    "bool" | "boolean" => ColumnType::Bool,
+                    "text" | "varchar" | "string" => {
+                        if self.eat_symbol("(") {
+                            let max = self.count()?;
+                            self.expect_symbol(")")?;
+                            ColumnType::Text(Some(max))
+                        } else {
+                            ColumnType::Text(None)
+                        }
+                    }
+                    _ => return Err(QueryError::Invalid(format!("Unknown column type '{}'", word))),
+                },
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected("a column type"));
+                }
+            };
+
+            // Columns are NOT NULL unless declared NULL
+            let nullable = if self.eat_keyword("not") {
+                self.expect_keyword("null")?;
+                false
+            } else {
+                self.eat_keyword("null")
+            };
+
+            columns.push(ColumnDef { name, ty, nullable });
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        Ok(columns)
+    }
+
+    fn end(&self) -> Result<(), QueryError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(QueryError::Syntax(format!("Unexpected {} after the end of the query", describe(token)))),
+        }
+    }
+
+    fn count(&mut self) -> Result<usize, QueryError> {
+        match self.next() {
+            Some(Token::Int(number)) if number >= 0 => Ok(number as usize),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("a non-negative number"))
+            }
+        }
+    }
+}
+
+fn negate(condition: Condition, negated: bool) -> Condition {
+    if negated {
+        Condition::Not(Box::new(condition))
+    } else {
+        condition
+    }
+}
+
+fn aggregate_fn(word: &str) -> Option<AggregateFn> {
+    match word.to_ascii_lowercase().as_str() {
+        "count" => Some(AggregateFn::Count),
+        "min" => Some(AggregateFn::Min),
+        "max" => Some(AggregateFn::Max),
+        "sum" => Some(AggregateFn::Sum),
+        "avg" => Some(AggregateFn::Avg),
+        _ => None,
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|keyword| word.eq_ignore_ascii_case(keyword))
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("'{}'", word),
+        Token::Int(number) => number.to_string(),
+        Token::Float(number) => number.to_string(),
+        Token::Str(text) => format!("'{}'", text),
+        Token::Symbol(symbol) => format!("'{}'", symbol),
+        Token::Param => "?".to_string(),
+    }
+}
+
+pub fn parse(input: &str) -> Result<Statement, QueryError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        params: None,
+    };
+    parser.statement()
+}
+
+// Parse a statement that may hold ? placeholders, returning how many it has.
+// They are numbered from 0 in the order they appear.
+pub fn parse_with_params(input: &str) -> Result<(Statement, usize), QueryError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        params: Some(0),
+    };
+    let statement = parser.statement()?;
+    Ok((statement, parser.params.unwrap_or_default()))
+}
+
+// Parse column definitions such as "name TEXT(32), age INT NULL"
+pub fn parse_columns(input: &str) -> Result<Vec<ColumnDef>, QueryError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        params: None,
+    };
+    let columns = parser.column_defs()?;
+    parser.end()?;
+    Ok(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: &str) -> String {
+        parse(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(error("SELECT * WHERE value = 'abc"), "Syntax error: Unterminated string");
+        assert_eq!(error("SELECT * WHERE key ~ 3"), "Syntax error: Unexpected character '~'");
+        assert_eq!(error("SELECT * WHERE key = 1 1"), "Syntax error: Unexpected 1 after the end of the query");
+        assert_eq!(error("INSERT (1, 'a')"), "Syntax error: Expected a column name but found 1");
+        assert_eq!(error("DELETE FROM"), "Syntax error: Expected a table name but the query ended");
+    }
+
+    #[test]
+    fn insert_with_ttl() {
+        match parse("INSERT INTO notes VALUES (1, 'it''s'), (2, 'b') TTL 60").unwrap() {
+            Statement::Insert { table, rows, ttl, upsert, .. } => {
+                assert_eq!(table.as_deref(), Some("notes"));
+                assert_eq!(rows.len(), 2);
+                assert_eq!(rows[0][1], Value::Text("it's".to_string()));
+                assert_eq!(ttl, Some(60));
+                assert!(!upsert);
+            }
+            statement => panic!("parsed as {:?}", statement),
+        }
+        assert_eq!(
+            error("INSERT VALUES (1, 'a') TTL"),
+            "Syntax error: Expected a non-negative number but the query ended"
+        );
+    }
+
+    #[test]
+    fn placeholders_are_counted() {
+        let (_, params) = parse_with_params("UPDATE SET value = ? WHERE key = ?").unwrap();
+        assert_eq!(params, 2);
+        assert!(parse("SELECT * WHERE key = ?").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{Map, Number};
+
+use crate::query::{self, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnType {
+    Int,
+    Float,
+    // Optional maximum length in characters
+    Text(Option<usize>),
+    Bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub ty: ColumnType,
+    pub nullable: bool,
+}
+
+// Named, typed columns stored in the value of each record. The key of the
+// record is not part of the schema and is always called "key".
+//
+// Rows are kept in the value as a JSON object, e.g. 1,{"name":"ann","age":41}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub columns: Vec<ColumnDef>,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnType::Int => write!(f, "INT"),
+            ColumnType::Float => write!(f, "FLOAT"),
+            ColumnType::Text(Some(max)) => write!(f, "TEXT({})", max),
+            ColumnType::Text(None) => write!(f, "TEXT"),
+            ColumnType::Bool => write!(f, "BOOL"),
+        }
+    }
+}
+
+impl fmt::Display for ColumnDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.name, self.ty)?;
+        if self.nullable {
+            write!(f, " NULL")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let columns: Vec<String> = self.columns.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", columns.join(", "))
+    }
+}
+
+// Parse a definition such as "name TEXT(32), age INT NULL, score FLOAT"
+impl FromStr for Schema {
+    type Err = String;
+
+    fn from_str(definition: &str) -> Result<Self, Self::Err> {
+        let columns = query::parse_columns(definition).map_err(|e| e.to_string())?;
+        Schema::new(columns)
+    }
+}
+
+impl Schema {
+    pub fn new(columns: Vec<ColumnDef>) -> Result<Schema, String> {
+        if columns.is_empty() {
+            return Err("A schema needs at least one column".to_string());
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if column.name.eq_ignore_ascii_case("key") {
+                return Err("'key' is reserved for the record key".to_string());
+            }
+            if columns[..i].iter().any(|c| c.name == column.name) {
+                return Err(format!("Column '{}' is defined twice", column.name));
+            }
+        }
+        Ok(Schema { columns })
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    // Check a row given in column order and encode it for storage
+    pub fn encode(&self, cells: &[Value]) -> Result<String, String> {
+        if cells.len() != self.columns.len() {
+            return Err(format!(
+                "Expected {} columns but got {}",
+                self.columns.len(),
+                cells.len()
+            ));
+        }
+
+        let mut object = Map::new();
+        for (column, cell) in self.columns.iter().zip(cells) {
+            let cell = column.check(cell)?;
+            let json = match cell {
+                Value::Int(number) => serde_json::Value::from(number),
+                Value::Float(number) => Number::from_f64(number)
+                    .map(serde_json::Value::Number)
+                    .ok_or_else(|| format!("Column '{}' cannot hold {}", column.name, number))?,
+                Value::Text(text) => serde_json::Value::String(text),
+                Value::Bool(flag) => serde_json::Value::Bool(flag),
+                // check refuses placeholders, they are never stored
+                Value::Null | Value::Param(_) => serde_json::Value::Null,
+            };
+            object.insert(column.name.clone(), json);
+        }
+
+        Ok(serde_json::Value::Object(object).to_string())
+    }
+
+    // Encode a row given as (column, value) pairs; columns left out are NULL
+    pub fn encode_fields(&self, fields: &[(String, Value)]) -> Result<String, String> {
+        let mut cells = vec![Value::Null; self.columns.len()];
+        for (name, value) in fields {
+            let index = self
+                .index_of(name)
+                .ok_or_else(|| format!("Unknown column '{}'", name))?;
+            cells[index] = value.clone();
+        }
+        self.encode(&cells)
+    }
+
+    // Read a stored row back into cells in column order
+    pub fn decode(&self, text: &str) -> Result<Vec<Value>, String> {
+        let object = match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(serde_json::Value::Object(object)) => object,
+            _ => return Err(format!("Value '{}' is not a row", text)),
+        };
+
+        if let Some(name) = object.keys().find(|name| self.index_of(name).is_none()) {
+            return Err(format!("Unknown column '{}'", name));
+        }
+
+        self.columns
+            .iter()
+            .map(|column| {
+                let cell = match object.get(&column.name) {
+                    Some(json) => value_from_json(json)
+                        .map_err(|e| format!("Column '{}' cannot hold {}", column.name, e))?,
+                    None => Value::Null,
+                };
+                column.check(&cell)
+            })
+            .collect()
+    }
+
+    // Parse a cell typed on the command line, e.g. "42", "true" or "null"
+    pub fn parse_cell(&self, index: usize, text: &str) -> Result<Value, String> {
+        let column = &self.columns[index];
+        if text.eq_ignore_ascii_case("null") {
+            return Ok(Value::Null);
+        }
+
+        let invalid = || format!("'{}' is not a valid {} for column '{}'", text, column.ty, column.name);
+        match column.ty {
+            ColumnType::Int => text.parse().map(Value::Int).map_err(|_| invalid()),
+            ColumnType::Float => text.parse().map(Value::Float).map_err(|_| invalid()),
+            ColumnType::Bool => match text.to_ascii_lowercase().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(invalid()),
+            },
+            ColumnType::Text(_) => Ok(Value::Text(text.to_string())),
+        }
+    }
+}
+
+// Convert a JSON scalar into a cell; arrays and objects are refused
+pub fn value_from_json(json: &serde_json::Value) -> Result<Value, String> {
+    match json {
+        serde_json::Value::Null => Ok(Value::Null),
+        serde_json::Value::Bool(flag) => Ok(Value::Bool(*flag)),
+        serde_json::Value::String(text) => Ok(Value::Text(text.clone())),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(int) => Ok(Value::Int(int)),
+            None => Ok(Value::Float(number.as_f64().unwrap_or_default())),
+        },
+        other => Err(other.to_string()),
+    }
+}
+
+impl ColumnDef {
+    // Check that the value fits the column, converting integers for FLOAT
+    // columns
+    fn check(&self, value: &Value) -> Result<Value, String> {
+        let mismatch = || format!("Column '{}' expects {} but got {}", self.name, self.ty, value);
+        match (&self.ty, value) {
+            (_, Value::Null) if self.nullable => Ok(Value::Null),
+            (_, Value::Null) => Err(format!("Column '{}' cannot be NULL", self.name)),
+            (ColumnType::Int, Value::Int(_)) => Ok(value.clone()),
+            (ColumnType::Float, Value::Float(_)) => Ok(value.clone()),
+            (ColumnType::Float, Value::Int(number)) => Ok(Value::Float(*number as f64)),
+            (ColumnType::Bool, Value::Bool(_)) => Ok(value.clone()),
+            (ColumnType::Text(max), Value::Text(text)) => match max {
+                Some(max) if text.chars().count() > *max => Err(format!(
+                    "Column '{}' holds at most {} characters",
+                    self.name, max
+                )),
+                _ => Ok(value.clone()),
+            },
+            _ => Err(mismatch()),
+        }
+    }
+}
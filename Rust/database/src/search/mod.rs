@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use crate::btree::Tree;
+
+// BM25 parameters: how quickly repeating a word stops raising the score, and
+// how much long values are penalized
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+// Split text into lowercase words, e.g. "Hello, World!" into "hello" and
+// "world"
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// A full-text index over the words of each record: an inverted index from
+// every word to the keys of the records containing it, kept in a B-Tree so
+// that the words starting with a prefix are next to each other.
+pub struct TextIndex {
+    // (word, key) -> how often the word occurs in the record
+    postings: Tree<(String, i32), usize>,
+    // The words of each key, to find its postings again on update
+    words: HashMap<i32, Vec<String>>,
+    // Sum of the number of words of all records
+    total_words: usize,
+}
+
+impl TextIndex {
+    pub fn new() -> Self {
+        TextIndex {
+            postings: Tree::new(),
+            words: HashMap::new(),
+            total_words: 0,
+        }
+    }
+
+    // Index the words of a record, replacing any earlier entry
+    pub fn insert(&mut self, key: i32, text: &str) {
+        self.remove(key);
+
+        let words = tokenize(text);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for word in &words {
+            *counts.entry(word).or_default() += 1;
+        }
+        for (word, count) in counts {
+            self.postings.insert((word.to_string(), key), count);
+        }
+
+        self.total_words += words.len();
+        self.words.insert(key, words);
+    }
+
+    pub fn remove(&mut self, key: i32) {
+        if let Some(words) = self.words.remove(&key) {
+            self.total_words -= words.len();
+            for word in words {
+                self.postings.delete(&(word, key));
+            }
+        }
+    }
+
+    // Keys of the records containing a word starting with each of the terms,
+    // best match first, with their BM25 score. Rare words and short records
+    // score higher.
+    pub fn search(&self, terms: &str) -> Vec<(i32, f64)> {
+        let terms = tokenize(terms);
+        if terms.is_empty() || self.words.is_empty() {
+            return Vec::new();
+        }
+
+        let records = self.words.len() as f64;
+        let average_words = self.total_words as f64 / records;
+        let mut scores: HashMap<i32, f64> = HashMap::new();
+        let mut matched: HashMap<i32, usize> = HashMap::new();
+
+        for (i, term) in terms.iter().enumerate() {
+            let mut term_scores: HashMap<i32, f64> = HashMap::new();
+            for (word, postings) in self.prefix(term) {
+                let idf = (1.0 + (records - postings.len() as f64 + 0.5) / (postings.len() as f64 + 0.5)).ln();
+                for (key, count) in postings {
+                    let length = self.words[&key].len() as f64;
+                    let count = count as f64;
+                    let score = idf * count * (K1 + 1.0) / (count + K1 * (1.0 - B + B * length / average_words));
+                    // A term matching a whole word counts more than a prefix
+                    let weight = if word == *term { 1.0 } else { 0.5 };
+                    *term_scores.entry(key).or_default() += weight * score;
+                }
+            }
+
+            // Every term has to match
+            for (key, score) in term_scores {
+                if matched.get(&key).copied().unwrap_or(0) == i {
+                    matched.insert(key, i + 1);
+                    *scores.entry(key).or_default() += score;
+                }
+            }
+        }
+
+        let mut results: Vec<(i32, f64)> = scores
+            .into_iter()
+            .filter(|(key, _)| matched[key] == terms.len())
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        results
+    }
+
+    // The words starting with a prefix, each with its (key, count) postings
+    fn prefix(&self, prefix: &str) -> Vec<(String, Vec<(i32, usize)>)> {
+        let start = Bound::Included((prefix.to_string(), i32::MIN));
+        let end = Bound::Excluded((format!("{}{}", prefix, char::MAX), i32::MIN));
+
+        let mut words: Vec<(String, Vec<(i32, usize)>)> = Vec::new();
+        for ((word, key), count) in self.postings.range((start, end)) {
+            match words.last_mut() {
+                Some((last, postings)) if *last == word => postings.push((key, count)),
+                _ => words.push((word, vec![(key, count)])),
+            }
+        }
+        words
+    }
+}
@@ -1,8 +1,17 @@
+use std::borrow::Cow;
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write};
 use std::path::Path;
+use std::time::SystemTime;
 
-use crate::btree::Record;
+use fs2::FileExt;
+
+use crate::btree::{Record, Version};
+use crate::schema::Schema;
+
+// First line of record and history files whose values are escaped. Files
+// written before escaping have no such line and are read as they are.
+const ESCAPED: &str = "#escaped";
 
 pub fn load_records(file_path: &str) -> io::Result<Vec<Record>> {
     let mut records = Vec::new();
@@ -12,8 +21,10 @@ pub fn load_records(file_path: &str) -> io::Result<Vec<Record>> {
         let mut data = String::new();
         file.read_to_string(&mut data)?;
 
+        let escaped = data.lines().next() == Some(ESCAPED);
         for line in data.lines() {
-            let parts: Vec<&str> = line.split(',').collect();
+            // Only the first comma separates the key, values may contain more
+            let parts: Vec<&str> = line.splitn(2, ',').collect();
             if parts.len() == 2 {
                 let key = parts[0].parse::<i32>().map_err(|e| {
                     io::Error::new(
@@ -21,7 +32,7 @@ pub fn load_records(file_path: &str) -> io::Result<Vec<Record>> {
                         format!("Invalid key in file: {}", e),
                     )
                 })?;
-                let value = parts[1].to_string();
+                let value = if escaped { unescape(parts[1]) } else { parts[1].to_string() };
                 records.push(Record { key, value });
             }
         }
@@ -37,9 +48,511 @@ pub fn save_records(file_path: &str, records: &[Record]) -> io::Result<()> {
         .create(true)
         .open(file_path)?;
 
+    writeln!(file, "{}", ESCAPED)?;
     for record in records {
-        writeln!(file, "{},{}", record.key, record.value)?;
+        writeln!(file, "{},{}", record.key, escape(&record.value))?;
+    }
+
+    Ok(())
+}
+
+// A value as it is written on its line: backslashes and line breaks are
+// escaped, so that a value holding a line break does not end its record
+fn escape(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '\n', '\r']) {
+        return Cow::Borrowed(value);
+    }
+    Cow::Owned(value.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r"))
+}
+
+// A value read back from its line. A backslash before any other character is
+// kept as it is.
+fn unescape(text: &str) -> String {
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => value.push('\\'),
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some(other) => {
+                value.push('\\');
+                value.push(other);
+            }
+            None => value.push('\\'),
+        }
+    }
+    value
+}
+
+// Versions are kept next to the database file, e.g. mydb.db -> mydb.history
+pub fn history_path(file_path: &str) -> String {
+    Path::new(file_path)
+        .with_extension("history")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn load_history(file_path: &str) -> io::Result<Option<Vec<Version>>> {
+    let path = history_path(file_path);
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+
+    let mut file = File::open(&path)?;
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+
+    let invalid = |what: &str, line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid {} in history file: {}", what, line),
+        )
+    };
+
+    let escaped = data.lines().next() == Some(ESCAPED);
+    let mut versions = Vec::new();
+    for line in data.lines() {
+        // version,timestamp,key,op[,value]
+        let parts: Vec<&str> = line.splitn(5, ',').collect();
+        if parts.len() < 4 {
+            continue;
+        }
+
+        let version = parts[0].parse::<u64>().map_err(|_| invalid("version", line))?;
+        let timestamp = parts[1].parse::<u64>().map_err(|_| invalid("timestamp", line))?;
+        let key = parts[2].parse::<i32>().map_err(|_| invalid("key", line))?;
+        let value = match parts[3] {
+            "set" if escaped => Some(unescape(parts.get(4).unwrap_or(&""))),
+            "set" => Some(parts.get(4).unwrap_or(&"").to_string()),
+            "del" => None,
+            _ => return Err(invalid("operation", line)),
+        };
+
+        versions.push(Version {
+            version,
+            timestamp,
+            key,
+            value,
+        });
+    }
+
+    Ok(Some(versions))
+}
+
+pub fn save_history(file_path: &str, versions: &[Version]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(history_path(file_path))?;
+
+    writeln!(file, "{}", ESCAPED)?;
+    for version in versions {
+        match &version.value {
+            Some(value) => writeln!(
+                file,
+                "{},{},{},set,{}",
+                version.version,
+                version.timestamp,
+                version.key,
+                escape(value)
+            )?,
+            None => writeln!(
+                file,
+                "{},{},{},del",
+                version.version, version.timestamp, version.key
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn remove_history(file_path: &str) -> io::Result<()> {
+    match std::fs::remove_file(history_path(file_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Expiry times are kept next to the database file, e.g. mydb.db -> mydb.ttl
+pub fn expiry_path(file_path: &str) -> String {
+    Path::new(file_path)
+        .with_extension("ttl")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Load (key, expires_at) pairs, in seconds since the epoch
+pub fn load_expiries(file_path: &str) -> io::Result<Vec<(i32, u64)>> {
+    let mut expiries = Vec::new();
+
+    let path = expiry_path(file_path);
+    if Path::new(&path).exists() {
+        let mut file = File::open(&path)?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+
+        for line in data.lines() {
+            if let Some((key, expires_at)) = line.split_once(',') {
+                let key = key.parse::<i32>();
+                let expires_at = expires_at.parse::<u64>();
+                match (key, expires_at) {
+                    (Ok(key), Ok(expires_at)) => expiries.push((key, expires_at)),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid expiry in file: {}", line),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(expiries)
+}
+
+// Write expiry times, removing the file when there are none
+pub fn save_expiries(file_path: &str, expiries: &[(i32, u64)]) -> io::Result<()> {
+    let path = expiry_path(file_path);
+    if expiries.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)?;
+
+    for (key, expires_at) in expiries {
+        writeln!(file, "{},{}", key, expires_at)?;
+    }
+
+    Ok(())
+}
+
+// The schema is kept next to the database file, e.g. mydb.db -> mydb.schema,
+// with one column definition per line
+pub fn schema_path(file_path: &str) -> String {
+    Path::new(file_path)
+        .with_extension("schema")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn load_schema(file_path: &str) -> io::Result<Option<Schema>> {
+    let path = schema_path(file_path);
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+
+    let mut file = File::open(&path)?;
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+
+    let definition: Vec<&str> = data.lines().filter(|line| !line.trim().is_empty()).collect();
+    definition
+        .join(", ")
+        .parse::<Schema>()
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid schema file: {}", e)))
+}
+
+// Write the schema, removing the file when there is none
+pub fn save_schema(file_path: &str, schema: Option<&Schema>) -> io::Result<()> {
+    let path = schema_path(file_path);
+    let schema = match schema {
+        Some(schema) => schema,
+        None => {
+            return match std::fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)?;
+
+    for column in &schema.columns {
+        writeln!(file, "{}", column)?;
+    }
+
+    Ok(())
+}
+
+// Columns with a secondary index, one per line, e.g. mydb.db -> mydb.indexes.
+// The indexes themselves are rebuilt from the records when loading.
+pub fn index_path(file_path: &str) -> String {
+    Path::new(file_path)
+        .with_extension("indexes")
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn load_indexes(file_path: &str) -> io::Result<Vec<String>> {
+    let path = index_path(file_path);
+    if !Path::new(&path).exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut file = File::open(&path)?;
+    let mut data = String::new();
+    file.read_to_string(&mut data)?;
+    Ok(data.lines().filter(|line| !line.is_empty()).map(String::from).collect())
+}
+
+// Write the indexed columns, removing the file when there are none
+pub fn save_indexes(file_path: &str, columns: &[String]) -> io::Result<()> {
+    let path = index_path(file_path);
+    if columns.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)?;
+
+    for column in columns {
+        writeln!(file, "{}", column)?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// The catalog lists the named tables of a database, one "name,file" line per
+// table, e.g. mydb.db -> mydb.catalog
+pub fn catalog_path(file_path: &str) -> String {
+    Path::new(file_path)
+        .with_extension("catalog")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// File holding the records of a table, e.g. mydb.db -> mydb.users.table. Its
+// history, expiries and schema sit next to it like those of a database.
+pub fn table_path(file_path: &str, table: &str) -> String {
+    Path::new(file_path)
+        .with_extension(format!("{}.table", table))
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Load (table, file) pairs. Files are listed relative to the database so
+// that the database can be moved along with its tables.
+pub fn load_catalog(file_path: &str) -> io::Result<Vec<(String, String)>> {
+    let mut tables = Vec::new();
+
+    let path = catalog_path(file_path);
+    if Path::new(&path).exists() {
+        let mut file = File::open(&path)?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+
+        for line in data.lines() {
+            match line.split_once(',') {
+                Some((name, file)) => {
+                    let table_file = Path::new(file_path).with_file_name(file);
+                    tables.push((name.to_string(), table_file.to_string_lossy().into_owned()));
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid table in catalog: {}", line),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(tables)
+}
+
+// Write the catalog, removing the file when there are no tables
+pub fn save_catalog(file_path: &str, tables: &[(String, String)]) -> io::Result<()> {
+    let path = catalog_path(file_path);
+    if tables.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)?;
+
+    for (name, table_file) in tables {
+        let table_file = Path::new(table_file).file_name().unwrap_or_default();
+        writeln!(file, "{},{}", name, table_file.to_string_lossy())?;
+    }
+
+    Ok(())
+}
+
+// Delete a table's records and every file kept next to them
+pub fn remove_table_files(table_file: &str) -> io::Result<()> {
+    let path = Path::new(table_file);
+    let files = [
+        path.to_path_buf(),
+        path.with_extension("history"),
+        path.with_extension("ttl"),
+        path.with_extension("schema"),
+        path.with_extension("indexes"),
+        path.with_extension("lock"),
+    ];
+
+    for file in files {
+        match std::fs::remove_file(file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// What a database file looked like when it was last read or written, used to
+// notice when another program changes it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+// Stamp of the file as it is now, or None if it does not exist
+pub fn file_stamp(file_path: &str) -> io::Result<Option<FileStamp>> {
+    match std::fs::metadata(file_path) {
+        Ok(metadata) => Ok(Some(FileStamp {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+        })),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Stamps of a database file and of the files kept next to it: its expiry
+// times, versions, schema and indexes
+pub fn database_stamp(file_path: &str) -> io::Result<Vec<Option<FileStamp>>> {
+    let paths = [
+        file_path.to_string(),
+        expiry_path(file_path),
+        history_path(file_path),
+        schema_path(file_path),
+        index_path(file_path),
+    ];
+    paths.iter().map(|path| file_stamp(path)).collect()
+}
+
+// Advisory lock on a database, held for as long as the value lives. The lock
+// is taken on a sidecar file (mydb.db -> mydb.lock) rather than the database
+// itself so that rewriting the database is never blocked by our own lock.
+pub struct DbLock {
+    file: File,
+}
+
+impl DbLock {
+    pub fn acquire(file_path: &str) -> io::Result<DbLock> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(Path::new(file_path).with_extension("lock"))?;
+
+        file.try_lock_exclusive().map_err(|error| {
+            if error.kind() == fs2::lock_contended_error().kind() {
+                io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("Database '{}' is locked by another process", file_path),
+                )
+            } else {
+                error
+            }
+        })?;
+
+        Ok(DbLock { file })
+    }
+}
+
+impl Drop for DbLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_with_line_breaks_survive_a_save() {
+        let dir = std::env::temp_dir().join(format!("ryou-storage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lines.db").to_string_lossy().into_owned();
+
+        let records = vec![
+            Record { key: 1, value: "line1\nline2".to_string() },
+            Record { key: 2, value: "a\r\nb".to_string() },
+            Record { key: 3, value: "C:\\new\\".to_string() },
+            Record { key: 4, value: "plain, with a comma".to_string() },
+        ];
+        save_records(&path, &records).unwrap();
+        let loaded = load_records(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let values: Vec<&str> = loaded.iter().map(|record| record.value.as_str()).collect();
+        assert_eq!(values, ["line1\nline2", "a\r\nb", "C:\\new\\", "plain, with a comma"]);
+    }
+
+    #[test]
+    fn files_without_the_marker_are_read_as_they_are() {
+        let dir = std::env::temp_dir().join(format!("ryou-storage-old-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("old.db").to_string_lossy().into_owned();
+        std::fs::write(&path, "1,C:\\new\\\n2,a\\nb\n").unwrap();
+        std::fs::write(history_path(&path), "1,100,2,set,a\\nb\n2,100,1,del\n").unwrap();
+
+        let records = load_records(&path).unwrap();
+        let values: Vec<String> = records.into_iter().map(|record| record.value).collect();
+        let history = load_history(&path).unwrap().unwrap();
+
+        // Saved again they are marked, and read back the same
+        save_records(&path, &[Record { key: 2, value: values[1].clone() }]).unwrap();
+        save_history(&path, &history).unwrap();
+        let reloaded = load_records(&path).unwrap();
+        let rehistory = load_history(&path).unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(values, [r"C:\new\", r"a\nb"]);
+        assert_eq!(history[0].value.as_deref(), Some(r"a\nb"));
+        assert_eq!(reloaded[0].value, r"a\nb");
+        assert_eq!(rehistory[0].value.as_deref(), Some(r"a\nb"));
+    }
+
+    #[test]
+    fn unknown_escapes_are_kept() {
+        assert_eq!(unescape("a\\tb\\"), "a\\tb\\");
+        assert_eq!(unescape(&escape("\\n is not a line break")), "\\n is not a line break");
+    }
+}
@@ -1,18 +1,47 @@
 // web.rs
-use actix_web::{web, App, HttpResponse, HttpServer, Responder, middleware};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder, middleware};
 use actix_cors::Cors;
 use actix_files as fs;
+use futures_util::stream;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::broadcast;
 
-use crate::btree::{BTree, Record};
-use crate::storage::{load_records, save_records};
+use crate::btree::{self, AsOf, Record, Version};
+use crate::config::Config;
+use crate::db::{Change, ChangeKind, Database, WriteMode, MAIN_TABLE};
+use crate::query::{
+    self, Aggregate, AggregateFn, Column, CompareOp, Condition, Explain, Join, JoinKind, Prepared, QueryError,
+    QueryOutput, Select, Source, Statement, Value,
+};
+use crate::schema::{value_from_json, Schema};
+
+// How often an idle change stream sends a comment to keep the connection open
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+// How often connected databases are checked for changes made by other programs
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+// How often expired records are deleted from connected databases
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+// Number of prepared statements kept at once, across all clients
+const MAX_STATEMENTS: usize = 1000;
 
 // Structure to hold our database connections
 struct AppState {
-    databases: Mutex<HashMap<String, BTree>>,
+    databases: Mutex<HashMap<String, Database>>,
+    // Every change to every connected database, tagged with its name
+    changes: broadcast::Sender<(String, Change)>,
+    // Prepared statements by id, with the database they belong to
+    statements: Mutex<HashMap<u64, (String, Prepared)>>,
+    next_statement: AtomicU64,
+    config: Config,
 }
 
 #[derive(Serialize)]
@@ -23,41 +52,492 @@ struct ApiResponse {
     data: Option<Vec<RecordDto>>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 struct RecordDto {
     key: i32,
-    value: String,
+    // Set for databases without a schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    // With a schema, each column is a field of the record
+    #[serde(flatten)]
+    columns: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct SchemaResponse {
+    success: bool,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    columns: Option<Vec<ColumnDto>>,
+}
+
+#[derive(Serialize)]
+struct ColumnDto {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+    nullable: bool,
 }
 
 #[derive(Deserialize)]
-struct ConnectRequest {
+struct SchemaRequest {
+    // e.g. "name TEXT(32), age INT NULL"; null removes the schema
+    schema: Option<String>,
+}
+
+// Path of a database, or of one of its tables under /tables/{table}
+#[derive(Deserialize)]
+struct TablePath {
     db_name: String,
+    table: Option<String>,
 }
 
 #[derive(Deserialize)]
-struct InsertRequest {
+struct RecordPath {
+    db_name: String,
+    table: Option<String>,
+    key: i32,
+}
+
+#[derive(Serialize)]
+struct TablesResponse {
+    success: bool,
+    message: String,
+    data: Vec<TableDto>,
+}
+
+#[derive(Serialize)]
+struct TableDto {
+    name: String,
+    records: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    // Columns with a secondary index
+    indexes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateTableRequest {
+    name: String,
+    // Column definitions as for PUT .../schema, none for plain records
+    schema: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ColumnsQuery {
+    // Comma-separated columns to return, e.g. ?columns=name,age
+    columns: Option<String>,
+    // Any other parameter only returns records whose column has that value,
+    // e.g. ?name=ann, using an index on the column if there is one
+    #[serde(flatten)]
+    filters: HashMap<String, String>,
+    // ?explain=true describes how the records are found instead
+    explain: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AggregateQuery {
+    // Comma-separated functions, e.g. ?ops=count,sum,avg
+    ops: Option<String>,
+    // Column to aggregate, the key by default
+    column: Option<String>,
+    // Inclusive key range
+    from: Option<String>,
+    to: Option<String>,
+    // Column filters as for GET .../records
+    #[serde(flatten)]
+    filters: HashMap<String, String>,
+    explain: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JoinQuery {
+    // Table to join with: a table of the same database, or database.table
+    with: String,
+    // inner (the default) or left
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    // Inclusive key range
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AggregateResponse {
+    success: bool,
+    message: String,
+    // One field per function, e.g. {"count": 3, "max": 41}
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    // Words to look for; each matches words starting with it
+    q: String,
+    // Return at most this many records, the best matches
+    limit: Option<usize>,
+    columns: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    success: bool,
+    message: String,
+    data: Vec<SearchHitDto>,
+}
+
+#[derive(Serialize)]
+struct SearchHitDto {
+    score: f64,
+    #[serde(flatten)]
+    record: RecordDto,
+}
+
+#[derive(Serialize)]
+struct HistoryResponse {
+    success: bool,
+    message: String,
+    data: Vec<VersionDto>,
+}
+
+#[derive(Serialize)]
+struct VersionDto {
+    version: u64,
+    timestamp: u64,
     key: i32,
-    value: String,
+    // None when the key was deleted in this version
+    value: Option<String>,
+}
+
+#[derive(Serialize)]
+struct QueryResponse {
+    success: bool,
+    message: String,
+    // Column names in the order they were selected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    columns: Option<Vec<String>>,
+    // One object per row, keyed by column name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<serde_json::Map<String, serde_json::Value>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explain: Option<Explain>,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    query: String,
+    // Explain the query instead of running it, like EXPLAIN <query>
+    #[serde(default)]
+    explain: bool,
+}
+
+#[derive(Serialize)]
+struct PrepareResponse {
+    success: bool,
+    message: String,
+    // Handle to run the statement with
+    id: u64,
+    // Number of ? placeholders to pass values for
+    params: usize,
 }
 
 #[derive(Deserialize)]
-struct KeyRequest {
+struct ExecuteRequest {
+    // A value for each placeholder, in order
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct ExplainResponse {
+    success: bool,
+    message: String,
+    explain: Explain,
+}
+
+#[derive(Serialize)]
+struct ChangeDto {
+    seq: u64,
+    table: String,
+    kind: &'static str,
     key: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    // Resume after this sequence number
+    since: Option<u64>,
+    // Only the changes to this table, e.g. "main" or "users"
+    table: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ConnectRequest {
+    db_name: String,
+    // Keep previous versions of records
+    #[serde(default)]
+    history: bool,
+    // Open without taking the lock; writes are refused
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Deserialize)]
+struct AsOfQuery {
+    as_of: Option<u64>,
+    version: Option<u64>,
+    columns: Option<String>,
+    #[serde(default)]
+    explain: bool,
+}
+
+#[derive(Deserialize)]
+struct InsertRequest {
+    // Taken from the path for PUT and PATCH
+    key: Option<i32>,
+    // For databases without a schema
+    value: Option<String>,
+    // Seconds until the record expires
+    ttl: Option<u64>,
+    // For databases with a schema, the columns of the row
+    #[serde(flatten)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+// Convert a record into a DTO, splitting rows into their columns. `columns`
+// picks which columns to include, all of them when None.
+fn record_dto(schema: Option<&Schema>, record: Record, columns: Option<&[String]>) -> Result<RecordDto, String> {
+    let schema = match schema {
+        Some(schema) => schema,
+        None => {
+            if let Some(name) = columns.into_iter().flatten().find(|name| *name != "value") {
+                return Err(format!("Unknown column '{}'", name));
+            }
+            return Ok(RecordDto {
+                key: record.key,
+                value: Some(record.value),
+                columns: serde_json::Map::new(),
+            });
+        }
+    };
+
+    let cells = schema.decode(&record.value)?;
+    let names = match columns {
+        Some(names) => names.to_vec(),
+        None => schema.names(),
+    };
+
+    let mut fields = serde_json::Map::new();
+    for name in names {
+        let index = schema
+            .index_of(&name)
+            .ok_or_else(|| format!("Unknown column '{}'", name))?;
+        fields.insert(name, serde_json::to_value(&cells[index]).unwrap_or_default());
+    }
+
+    Ok(RecordDto {
+        key: record.key,
+        value: None,
+        columns: fields,
+    })
+}
+
+// Condition requiring each column of `filters` to equal its value, None when
+// there are no filters
+fn value_filter(schema: Option<&Schema>, filters: &HashMap<String, String>) -> Result<Option<Condition>, String> {
+    let mut names: Vec<&String> = filters.keys().collect();
+    names.sort();
+
+    let mut filter = None;
+    for name in names {
+        let text = &filters[name];
+        let value = match schema {
+            Some(schema) => {
+                let index = schema
+                    .index_of(name)
+                    .ok_or_else(|| format!("Unknown column '{}'", name))?;
+                schema.parse_cell(index, text)?
+            }
+            None if name == "value" => Value::Text(text.clone()),
+            None => return Err(format!("Unknown column '{}'", name)),
+        };
+
+        let condition = Condition::Compare {
+            column: Column::Named(name.clone()),
+            op: CompareOp::Eq,
+            value,
+        };
+        filter = Some(match filter {
+            Some(filter) => Condition::And(Box::new(filter), Box::new(condition)),
+            None => condition,
+        });
+    }
+    Ok(filter)
+}
+
+// The aggregates and filter asked for by an /aggregate request
+fn aggregate_request(
+    schema: Option<&Schema>,
+    query: &AggregateQuery,
+) -> Result<(Vec<Aggregate>, Option<Condition>), String> {
+    let column = match query.column.as_deref() {
+        None | Some("key") => Column::Key,
+        Some(name) => Column::Named(name.to_string()),
+    };
+
+    let mut aggregates = Vec::new();
+    for op in query.ops.as_deref().unwrap_or("count,min,max").split(',') {
+        let op = op.trim().to_ascii_lowercase();
+        let function = match op.as_str() {
+            "count" => AggregateFn::Count,
+            "min" => AggregateFn::Min,
+            "max" => AggregateFn::Max,
+            "sum" => AggregateFn::Sum,
+            "avg" => AggregateFn::Avg,
+            _ => return Err(format!("Unknown aggregate '{}', expected count, min, max, sum or avg", op)),
+        };
+        aggregates.push(Aggregate { function, column: Some(column.clone()) });
+    }
+
+    let filter = value_filter(schema, &query.filters)?;
+    Ok((aggregates, key_range(filter, &query.from, &query.to)?))
+}
+
+// Add the limits of ?from=<key>&to=<key> to a filter
+fn key_range(
+    mut filter: Option<Condition>,
+    from: &Option<String>,
+    to: &Option<String>,
+) -> Result<Option<Condition>, String> {
+    for (text, op) in [(from, CompareOp::Ge), (to, CompareOp::Le)] {
+        if let Some(text) = text {
+            let key: i32 = text.parse().map_err(|_| format!("Invalid key '{}'", text))?;
+            let condition = Condition::Compare {
+                column: Column::Key,
+                op,
+                value: Value::Int(key as i64),
+            };
+            filter = Some(match filter {
+                Some(filter) => Condition::And(Box::new(filter), Box::new(condition)),
+                None => condition,
+            });
+        }
+    }
+    Ok(filter)
+}
+
+// Whether a flag such as ?explain=true is set. Flags of queries that also take
+// column filters arrive as text.
+fn is_set(flag: &Option<String>) -> bool {
+    matches!(flag.as_deref(), Some("true") | Some("1"))
+}
+
+// A SELECT * reading the records matching a filter
+fn select_statement(filter: Option<Condition>) -> Statement {
+    Statement::Select(Select {
+        table: None,
+        columns: Vec::new(),
+        filter,
+        order_by: None,
+        limit: None,
+        offset: 0,
+    })
+}
+
+// Response for ?explain=true: how the statement finds its records
+fn explain_response(db: &Database, statement: &Statement) -> HttpResponse {
+    match query::explain(db, statement) {
+        Ok(explain) => HttpResponse::Ok().json(ExplainResponse {
+            success: true,
+            message: format!("Explained {}", explain.access_path),
+            explain,
+        }),
+        Err(error) => HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: error.to_string(),
+            data: None,
+        }),
+    }
 }
 
-// Helper function to convert between domain Record and DTO
-impl From<Record> for RecordDto {
-    fn from(record: Record) -> Self {
-        RecordDto {
-            key: record.key,
-            value: record.value,
+// Split a ?columns=a,b parameter
+fn column_list(columns: &Option<String>) -> Option<Vec<String>> {
+    columns
+        .as_ref()
+        .map(|list| list.split(',').map(|name| name.trim().to_string()).collect())
+}
+
+// The value to store for an insert: the row built from the fields of the
+// request with a schema, the plain value without one
+fn insert_value(schema: Option<&Schema>, req: &InsertRequest) -> Result<String, String> {
+    match (schema, &req.value) {
+        (Some(_), Some(_)) => Err("This database has a schema, send the columns as fields instead of a value".to_string()),
+        (Some(schema), None) => {
+            let fields = req
+                .fields
+                .iter()
+                .map(|(name, json)| {
+                    let value = value_from_json(json).map_err(|e| format!("Column '{}' cannot hold {}", name, e))?;
+                    Ok((name.clone(), value))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            schema.encode_fields(&fields)
+        }
+        (None, Some(value)) => match req.fields.keys().next() {
+            Some(name) => Err(format!("Unknown field '{}'", name)),
+            None => Ok(value.clone()),
+        },
+        (None, None) => Err("Missing value".to_string()),
+    }
+}
+
+impl From<Version> for VersionDto {
+    fn from(version: Version) -> Self {
+        VersionDto {
+            version: version.version,
+            timestamp: version.timestamp,
+            key: version.key,
+            value: version.value,
+        }
+    }
+}
+
+
+impl From<&Change> for ChangeDto {
+    fn from(change: &Change) -> Self {
+        ChangeDto {
+            seq: change.seq,
+            table: change.table.clone(),
+            kind: match change.kind {
+                ChangeKind::Insert => "insert",
+                ChangeKind::Update => "update",
+                ChangeKind::Delete => "delete",
+            },
+            key: change.key,
+            value: change.value.clone(),
         }
     }
 }
 
 // Serve static files (HTML, CSS, JS)
-async fn index() -> impl Responder {
-    fs::NamedFile::open_async("./static/index.html").await
+async fn index(data: web::Data<AppState>) -> impl Responder {
+    fs::NamedFile::open_async(data.config.static_dir.join("index.html")).await
+}
+
+// Open a database of the data directory, read-only if asked or if the server
+// is, and forward its changes to anyone watching it
+fn open_database(data: &AppState, db_name: &str, read_only: bool) -> io::Result<Database> {
+    let mut db = Database::open(&data.config.database_path(db_name), read_only || data.config.read_only)?;
+    let sender = data.changes.clone();
+    let name = db_name.to_string();
+    db.on_change(Box::new(move |change| {
+        // Sending only fails when nobody is watching
+        let _ = sender.send((name.clone(), change.clone()));
+    }));
+    Ok(db)
 }
 
 // API endpoint to connect to a database
@@ -66,12 +546,22 @@ async fn connect_database(
     req: web::Json<ConnectRequest>,
 ) -> impl Responder {
     let db_name = &req.db_name;
-    let file_path = format!("{}.db", db_name);
+    let file_path = data.config.database_path(db_name);
     
     let mut databases = data.databases.lock().unwrap();
     
     // Check if we're already connected to this DB
-    if databases.contains_key(db_name) {
+    if let Some(db) = databases.get_mut(db_name) {
+        if req.history && !db.tree().history_enabled() {
+            if let Err(error) = db.set_history(true) {
+                return HttpResponse::InternalServerError().json(ApiResponse {
+                    success: false,
+                    message: format!("Failed to enable history: {}", error),
+                    data: None,
+                });
+            }
+        }
+
         return HttpResponse::Ok().json(ApiResponse {
             success: true,
             message: format!("Already connected to database: {}", db_name),
@@ -79,59 +569,192 @@ async fn connect_database(
         });
     }
     
-    // Try to load records from the database file
-    match load_records(&file_path) {
-        Ok(records) => {
-            let mut tree = BTree::new();
+    // If the file doesn't exist yet, opening it creates a new empty database
+    let created = !Path::new(&file_path).exists();
+    
+    match open_database(&data, db_name, req.read_only) {
+        Ok(mut db) => {
+            if req.history && !db.tree().history_enabled() {
+                if let Err(error) = db.set_history(true) {
+                    return HttpResponse::InternalServerError().json(ApiResponse {
+                        success: false,
+                        message: format!("Failed to enable history: {}", error),
+                        data: None,
+                    });
+                }
+            }
             
-            // Insert all records into the tree
-            for record in records {
-                tree.insert(record.key, record.value);
+            let mut message = if created {
+                format!("Created new database: {}", db_name)
+            } else {
+                format!("Connected to database: {}", db_name)
+            };
+            if db.is_read_only() {
+                message.push_str(" (read-only)");
             }
             
-            // Store the tree in our app state
-            databases.insert(db_name.clone(), tree);
+            // Store the database in our app state
+            databases.insert(db_name.clone(), db);
             
             HttpResponse::Ok().json(ApiResponse {
                 success: true,
-                message: format!("Connected to database: {}", db_name),
+                message,
+                data: None,
+            })
+        }
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+            // Another process (e.g. the CLI) is writing to this database
+            HttpResponse::Locked().json(ApiResponse {
+                success: false,
+                message: format!("{}. Connect with read_only to open it without writing", error),
                 data: None,
             })
         }
         Err(error) => {
-            if error.kind() == io::ErrorKind::NotFound {
-                // If file doesn't exist, create a new empty database
-                let tree = BTree::new();
-                databases.insert(db_name.clone(), tree);
-                
-                HttpResponse::Ok().json(ApiResponse {
-                    success: true,
-                    message: format!("Created new database: {}", db_name),
-                    data: None,
-                })
-            } else {
-                // If there was another error, return it
-                HttpResponse::InternalServerError().json(ApiResponse {
-                    success: false,
-                    message: format!("Failed to connect to database: {}", error),
-                    data: None,
-                })
-            }
+            HttpResponse::InternalServerError().json(ApiResponse {
+                success: false,
+                message: format!("Failed to connect to database: {}", error),
+                data: None,
+            })
         }
     }
 }
 
+// The database, or its table when one is named in the path
+fn find_table<'a>(
+    databases: &'a mut HashMap<String, Database>,
+    db_name: &str,
+    table: Option<&str>,
+) -> Option<&'a mut Database> {
+    let db = databases.get_mut(db_name)?;
+    match table {
+        Some(table) => db.table_mut(table),
+        None => Some(db),
+    }
+}
+
+// "shop" for a database, "shop/users" for one of its tables
+fn table_label(db_name: &str, table: Option<&str>) -> String {
+    match table {
+        Some(table) => format!("{}/{}", db_name, table),
+        None => db_name.to_string(),
+    }
+}
+
+fn not_found_message(db_name: &str, table: Option<&str>) -> String {
+    match table {
+        Some(table) => format!("Table '{}' not found in database '{}'", table, db_name),
+        None => format!("Database '{}' not found", db_name),
+    }
+}
+
+// Errors caused by the request rather than the server, e.g. a row that does
+// not fit the schema or a table that already exists
+fn is_client_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::AlreadyExists | io::ErrorKind::NotFound
+    )
+}
+
+// Response for writes to a database opened read-only
+fn read_only_response(db_name: &str) -> HttpResponse {
+    HttpResponse::Locked().json(ApiResponse {
+        success: false,
+        message: format!("Database '{}' is open read-only", db_name),
+        data: None,
+    })
+}
+
+// Reload a database whose file was changed by another program (the CLI,
+// app.py, a restore), so that we neither serve nor overwrite stale records.
+// Returns whether a reload happened.
+fn reload_if_changed(db_name: &str, db: &mut Database) -> io::Result<bool> {
+    let reloaded = db.reload_if_changed()?;
+    if reloaded {
+        println!("Database '{}' was changed on disk, reloaded it", db_name);
+    }
+    Ok(reloaded)
+}
+
+fn reload_failed_response(db_name: &str, error: io::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().json(ApiResponse {
+        success: false,
+        message: format!("Failed to reload database '{}': {}", db_name, error),
+        data: None,
+    })
+}
+
+// Message suffix telling the client its write was applied on top of changes
+// another program made to the file
+fn reload_note(reloaded: bool) -> &'static str {
+    if reloaded {
+        " (database file was changed on disk and has been reloaded first)"
+    } else {
+        ""
+    }
+}
+
 // API endpoint to get all records
 async fn get_all_records(
     data: web::Data<AppState>,
-    path: web::Path<String>,
+    path: web::Path<TablePath>,
+    query: web::Query<ColumnsQuery>,
 ) -> impl Responder {
-    let db_name = path.into_inner();
-    let databases = data.databases.lock().unwrap();
+    let TablePath { db_name, table } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
     
-    if let Some(tree) = databases.get(&db_name) {
-        let records = tree.get_all_records();
-        let records_dto: Vec<RecordDto> = records.into_iter().map(|r| r.into()).collect();
+    if let Some(db) = find_table(&mut databases, &db_name, table.as_deref()) {
+        if let Err(error) = reload_if_changed(&db_name, db) {
+            return reload_failed_response(&db_name, error);
+        }
+        
+        if is_set(&query.explain) {
+            return match value_filter(db.schema(), &query.filters) {
+                Ok(filter) => explain_response(db, &select_statement(filter)),
+                Err(message) => HttpResponse::BadRequest().json(ApiResponse {
+                    success: false,
+                    message,
+                    data: None,
+                }),
+            };
+        }
+
+        let columns = column_list(&query.columns);
+        let records = match value_filter(db.schema(), &query.filters) {
+            Ok(None) => db.tree().get_all_records(),
+            Ok(Some(filter)) => match query::find_records(db, &filter) {
+                Ok(records) => records,
+                Err(error) => {
+                    return HttpResponse::BadRequest().json(ApiResponse {
+                        success: false,
+                        message: error.to_string(),
+                        data: None,
+                    });
+                }
+            },
+            Err(message) => {
+                return HttpResponse::BadRequest().json(ApiResponse {
+                    success: false,
+                    message,
+                    data: None,
+                });
+            }
+        };
+        let records_dto = match records
+            .into_iter()
+            .map(|r| record_dto(db.schema(), r, columns.as_deref()))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(records_dto) => records_dto,
+            Err(message) => {
+                return HttpResponse::BadRequest().json(ApiResponse {
+                    success: false,
+                    message,
+                    data: None,
+                });
+            }
+        };
         
         HttpResponse::Ok().json(ApiResponse {
             success: true,
@@ -141,74 +764,399 @@ async fn get_all_records(
     } else {
         HttpResponse::NotFound().json(ApiResponse {
             success: false,
-            message: format!("Database '{}' not found", db_name),
+            message: not_found_message(&db_name, table.as_deref()),
             data: None,
         })
     }
 }
 
-// API endpoint to find a record by key
-async fn find_record(
+// API endpoint to search the words of the records, e.g. ?q=green tea&limit=10.
+// Results are ranked, best match first.
+async fn search_records(
     data: web::Data<AppState>,
-    path: web::Path<(String, i32)>,
+    path: web::Path<TablePath>,
+    query: web::Query<SearchQuery>,
 ) -> impl Responder {
-    let (db_name, key) = path.into_inner();
-    let databases = data.databases.lock().unwrap();
-    
-    if let Some(tree) = databases.get(&db_name) {
-        if let Some(value) = tree.search(key) {
-            let record = RecordDto { key, value };
-            HttpResponse::Ok().json(ApiResponse {
-                success: true,
-                message: format!("Found record with key {}", key),
-                data: Some(vec![record]),
-            })
-        } else {
-            HttpResponse::NotFound().json(ApiResponse {
+    let TablePath { db_name, table } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match find_table(&mut databases, &db_name, table.as_deref()) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
                 success: false,
-                message: format!("Record with key {} not found", key),
+                message: not_found_message(&db_name, table.as_deref()),
                 data: None,
-            })
+            });
         }
-    } else {
-        HttpResponse::NotFound().json(ApiResponse {
+    };
+
+    if let Err(error) = reload_if_changed(&db_name, db) {
+        return reload_failed_response(&db_name, error);
+    }
+
+    let columns = column_list(&query.columns);
+    let hits = db
+        .search(&query.q)
+        .into_iter()
+        .take(query.limit.unwrap_or(usize::MAX))
+        .map(|(record, score)| {
+            let record = record_dto(db.schema(), record, columns.as_deref())?;
+            Ok(SearchHitDto { score, record })
+        })
+        .collect::<Result<Vec<_>, String>>();
+
+    match hits {
+        Ok(hits) => HttpResponse::Ok().json(SearchResponse {
+            success: true,
+            message: format!("Found {} records", hits.len()),
+            data: hits,
+        }),
+        Err(message) => HttpResponse::BadRequest().json(ApiResponse {
             success: false,
-            message: format!("Database '{}' not found", db_name),
+            message,
             data: None,
-        })
+        }),
     }
 }
 
-// API endpoint to insert a record
-async fn insert_record(
+// API endpoint to aggregate the records in a key range, e.g.
+// ?ops=count,avg&column=age&from=10&to=20
+async fn aggregate_records(
     data: web::Data<AppState>,
-    path: web::Path<String>,
-    req: web::Json<InsertRequest>,
+    path: web::Path<TablePath>,
+    query: web::Query<AggregateQuery>,
 ) -> impl Responder {
-    let db_name = path.into_inner();
+    let TablePath { db_name, table } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match find_table(&mut databases, &db_name, table.as_deref()) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(AggregateResponse {
+                success: false,
+                message: not_found_message(&db_name, table.as_deref()),
+                data: None,
+            });
+        }
+    };
+
+    if let Err(error) = reload_if_changed(&db_name, db) {
+        return reload_failed_response(&db_name, error);
+    }
+
+    if is_set(&query.explain) {
+        return match aggregate_request(db.schema(), &query) {
+            Ok((aggregates, filter)) => {
+                explain_response(db, &Statement::Aggregate { table: None, aggregates, filter })
+            }
+            Err(message) => HttpResponse::BadRequest().json(AggregateResponse {
+                success: false,
+                message,
+                data: None,
+            }),
+        };
+    }
+
+    let result = aggregate_request(db.schema(), &query).and_then(|(aggregates, filter)| {
+        let values = query::aggregate(db, &aggregates, filter.as_ref()).map_err(|e| e.to_string())?;
+        Ok(aggregates
+            .iter()
+            .map(|a| a.function.name().to_ascii_lowercase())
+            .zip(values.into_iter().map(|value| serde_json::to_value(value).unwrap_or_default()))
+            .collect())
+    });
+
+    match result {
+        Ok(results) => HttpResponse::Ok().json(AggregateResponse {
+            success: true,
+            message: format!("Aggregated {}", table_label(&db_name, table.as_deref())),
+            data: Some(results),
+        }),
+        Err(message) => HttpResponse::BadRequest().json(AggregateResponse {
+            success: false,
+            message,
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to find a record by key, optionally as it was at a past
+// time (?as_of=<timestamp>) or version (?version=<n>)
+async fn find_record(
+    data: web::Data<AppState>,
+    path: web::Path<RecordPath>,
+    query: web::Query<AsOfQuery>,
+) -> impl Responder {
+    let RecordPath { db_name, table, key } = path.into_inner();
     let mut databases = data.databases.lock().unwrap();
     
-    if let Some(tree) = databases.get_mut(&db_name) {
-        let file_path = format!("{}.db", db_name);
+    if let Some(db) = find_table(&mut databases, &db_name, table.as_deref()) {
+        if let Err(error) = reload_if_changed(&db_name, db) {
+            return reload_failed_response(&db_name, error);
+        }
         
-        // Check if key already exists
-        let updating = tree.search(req.key).is_some();
+        if query.explain {
+            let filter = Condition::Compare {
+                column: Column::Key,
+                op: CompareOp::Eq,
+                value: Value::Int(key as i64),
+            };
+            return explain_response(db, &select_statement(Some(filter)));
+        }
+
+        let tree = db.tree();
+        let as_of = match (query.version, query.as_of) {
+            (Some(version), _) => Some(AsOf::Version(version)),
+            (None, Some(timestamp)) => Some(AsOf::Time(timestamp)),
+            (None, None) => None,
+        };
+
+        if as_of.is_some() && !tree.history_enabled() {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: format!("History is not enabled for database '{}'", db_name),
+                data: None,
+            });
+        }
+
+        let found = match as_of {
+            Some(as_of) => tree.search_as_of(key, as_of),
+            None => tree.search(key),
+        };
+
+        if let Some(value) = found {
+            let columns = column_list(&query.columns);
+            match record_dto(db.schema(), Record { key, value }, columns.as_deref()) {
+                Ok(record) => HttpResponse::Ok().json(ApiResponse {
+                    success: true,
+                    message: format!("Found record with key {}", key),
+                    data: Some(vec![record]),
+                }),
+                Err(message) => HttpResponse::BadRequest().json(ApiResponse {
+                    success: false,
+                    message,
+                    data: None,
+                }),
+            }
+        } else {
+            HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Record with key {} not found", key),
+                data: None,
+            })
+        }
+    } else {
+        HttpResponse::NotFound().json(ApiResponse {
+            success: false,
+            message: not_found_message(&db_name, table.as_deref()),
+            data: None,
+        })
+    }
+}
+
+// API endpoint to list the changes made to a key
+async fn record_history(
+    data: web::Data<AppState>,
+    path: web::Path<RecordPath>,
+) -> impl Responder {
+    let RecordPath { db_name, table, key } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    if let Some(db) = find_table(&mut databases, &db_name, table.as_deref()) {
+        if let Err(error) = reload_if_changed(&db_name, db) {
+            return reload_failed_response(&db_name, error);
+        }
+    }
+
+    match find_table(&mut databases, &db_name, table.as_deref()) {
+        Some(db) if db.tree().history_enabled() => {
+            let versions: Vec<VersionDto> = db.tree().history(key).into_iter().map(|v| v.into()).collect();
+
+            HttpResponse::Ok().json(HistoryResponse {
+                success: true,
+                message: format!("Retrieved {} versions of key {}", versions.len(), key),
+                data: versions,
+            })
+        }
+        Some(_) => HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: format!("History is not enabled for database '{}'", db_name),
+            data: None,
+        }),
+        None => HttpResponse::NotFound().json(ApiResponse {
+            success: false,
+            message: not_found_message(&db_name, table.as_deref()),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to insert a new record. Fails with 409 if the key exists.
+async fn insert_record(
+    data: web::Data<AppState>,
+    path: web::Path<TablePath>,
+    req: web::Json<InsertRequest>,
+) -> impl Responder {
+    let TablePath { db_name, table } = path.into_inner();
+    match req.key {
+        Some(key) => write_record(&data, &db_name, table.as_deref(), key, &req, WriteMode::Insert),
+        None => HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: "Missing key".to_string(),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to insert a record or replace the existing one (PUT)
+async fn upsert_record(
+    data: web::Data<AppState>,
+    path: web::Path<RecordPath>,
+    req: web::Json<InsertRequest>,
+) -> impl Responder {
+    let RecordPath { db_name, table, key } = path.into_inner();
+    write_record(&data, &db_name, table.as_deref(), key, &req, WriteMode::Upsert)
+}
+
+// API endpoint to replace an existing record (PATCH). Fails with 404 if the
+// key does not exist.
+async fn update_record(
+    data: web::Data<AppState>,
+    path: web::Path<RecordPath>,
+    req: web::Json<InsertRequest>,
+) -> impl Responder {
+    let RecordPath { db_name, table, key } = path.into_inner();
+    write_record(&data, &db_name, table.as_deref(), key, &req, WriteMode::Update)
+}
+
+fn write_record(
+    data: &AppState,
+    db_name: &str,
+    table: Option<&str>,
+    key: i32,
+    req: &InsertRequest,
+    mode: WriteMode,
+) -> HttpResponse {
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match find_table(&mut databases, db_name, table) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: not_found_message(db_name, table),
+                data: None,
+            });
+        }
+    };
+
+    if db.is_read_only() {
+        return read_only_response(db_name);
+    }
+
+    if req.key.is_some_and(|body_key| body_key != key) {
+        return HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: format!("The key in the body does not match key {} in the path", key),
+            data: None,
+        });
+    }
+
+    let reloaded = match reload_if_changed(db_name, db) {
+        Ok(reloaded) => reloaded,
+        Err(error) => return reload_failed_response(db_name, error),
+    };
+
+    let value = match insert_value(db.schema(), req) {
+        Ok(value) => value,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message,
+                data: None,
+            });
+        }
+    };
+
+    // Write the record and save changes to disk, noting whether it replaced
+    // an existing one
+    match db.put(key, value, req.ttl, mode) {
+        Ok(previous) => {
+            let message = if previous.is_some() {
+                format!("Updated record with key {}{}", key, reload_note(reloaded))
+            } else {
+                format!("Inserted new record with key {}{}", key, reload_note(reloaded))
+            };
+
+            HttpResponse::Ok().json(ApiResponse {
+                success: true,
+                message,
+                data: None,
+            })
+        }
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            HttpResponse::Conflict().json(ApiResponse {
+                success: false,
+                message: format!("{}, use PUT to replace it", error),
+                data: None,
+            })
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: error.to_string(),
+                data: None,
+            })
+        }
+        Err(error) if is_client_error(&error) => {
+            HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: error.to_string(),
+                data: None,
+            })
+        }
+        Err(error) => {
+            HttpResponse::InternalServerError().json(ApiResponse {
+                success: false,
+                message: format!("Failed to save changes: {}", error),
+                data: None,
+            })
+        }
+    }
+}
+
+// API endpoint to delete a record
+async fn delete_record(
+    data: web::Data<AppState>,
+    path: web::Path<RecordPath>,
+) -> impl Responder {
+    let RecordPath { db_name, table, key } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+    
+    if let Some(db) = find_table(&mut databases, &db_name, table.as_deref()) {
+        if db.is_read_only() {
+            return read_only_response(&db_name);
+        }
         
-        // Insert the record
-        tree.insert(req.key, req.value.clone());
+        let reloaded = match reload_if_changed(&db_name, db) {
+            Ok(reloaded) => reloaded,
+            Err(error) => return reload_failed_response(&db_name, error),
+        };
         
-        // Save changes to disk
-        match save_records(&file_path, &tree.get_all_records()) {
-            Ok(_) => {
-                let message = if updating {
-                    format!("Updated record with key {}", req.key)
-                } else {
-                    format!("Inserted new record with key {}", req.key)
-                };
-                
+        // Try to delete the record and save changes to disk
+        match db.delete(key) {
+            Ok(true) => {
                 HttpResponse::Ok().json(ApiResponse {
                     success: true,
-                    message,
+                    message: format!("Deleted record with key {}{}", key, reload_note(reloaded)),
+                    data: None,
+                })
+            }
+            Ok(false) => {
+                HttpResponse::NotFound().json(ApiResponse {
+                    success: false,
+                    message: format!("Record with key {} not found", key),
                     data: None,
                 })
             }
@@ -223,70 +1171,781 @@ async fn insert_record(
     } else {
         HttpResponse::NotFound().json(ApiResponse {
             success: false,
-            message: format!("Database '{}' not found", db_name),
+            message: not_found_message(&db_name, table.as_deref()),
             data: None,
         })
     }
 }
 
-// API endpoint to delete a record
-async fn delete_record(
+fn schema_response(message: String, schema: Option<&Schema>) -> SchemaResponse {
+    SchemaResponse {
+        success: true,
+        message,
+        columns: Some(
+            schema
+                .map(|schema| {
+                    schema
+                        .columns
+                        .iter()
+                        .map(|column| ColumnDto {
+                            name: column.name.clone(),
+                            ty: column.ty.to_string(),
+                            nullable: column.nullable,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+    }
+}
+
+// API endpoint to get the columns of a database, an empty list without a
+// schema
+async fn get_schema(
+    data: web::Data<AppState>,
+    path: web::Path<TablePath>,
+) -> impl Responder {
+    let TablePath { db_name, table } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    match find_table(&mut databases, &db_name, table.as_deref()) {
+        Some(db) => {
+            if let Err(error) = reload_if_changed(&db_name, db) {
+                return reload_failed_response(&db_name, error);
+            }
+            let message = match db.schema() {
+                Some(schema) => format!("Schema of '{}': {}", table_label(&db_name, table.as_deref()), schema),
+                None => format!("'{}' has no schema", table_label(&db_name, table.as_deref())),
+            };
+            HttpResponse::Ok().json(schema_response(message, db.schema()))
+        }
+        None => HttpResponse::NotFound().json(ApiResponse {
+            success: false,
+            message: not_found_message(&db_name, table.as_deref()),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to set or remove the schema of a database. Existing records
+// must fit the new schema.
+async fn set_schema(
     data: web::Data<AppState>,
-    path: web::Path<(String, i32)>,
+    path: web::Path<TablePath>,
+    req: web::Json<SchemaRequest>,
 ) -> impl Responder {
-    let (db_name, key) = path.into_inner();
+    let TablePath { db_name, table } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match find_table(&mut databases, &db_name, table.as_deref()) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: not_found_message(&db_name, table.as_deref()),
+                data: None,
+            });
+        }
+    };
+
+    if db.is_read_only() {
+        return read_only_response(&db_name);
+    }
+
+    let schema = match req.schema.as_deref().map(str::parse::<Schema>).transpose() {
+        Ok(schema) => schema,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message,
+                data: None,
+            });
+        }
+    };
+
+    match db.set_schema(schema) {
+        Ok(()) => {
+            let message = match db.schema() {
+                Some(schema) => format!("Schema of '{}' set to {}", table_label(&db_name, table.as_deref()), schema),
+                None => format!("Removed the schema of '{}'", table_label(&db_name, table.as_deref())),
+            };
+            HttpResponse::Ok().json(schema_response(message, db.schema()))
+        }
+        Err(error) if is_client_error(&error) => {
+            HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: error.to_string(),
+                data: None,
+            })
+        }
+        Err(error) => HttpResponse::InternalServerError().json(ApiResponse {
+            success: false,
+            message: format!("Failed to save schema: {}", error),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to list the tables of a database, starting with the main one
+async fn list_tables(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match databases.get_mut(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+
+    if let Err(error) = reload_if_changed(&db_name, db) {
+        return reload_failed_response(&db_name, error);
+    }
+
+    let mut names = vec![MAIN_TABLE.to_string()];
+    names.extend(db.table_names());
+    let tables: Vec<TableDto> = names
+        .into_iter()
+        .filter_map(|name| {
+            let table = db.table(&name)?;
+            Some(TableDto {
+                records: table.tree().get_all_records().len(),
+                schema: table.schema().map(|schema| schema.to_string()),
+                indexes: table.indexes().iter().map(|i| i.column().to_string()).collect(),
+                name,
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(TablesResponse {
+        success: true,
+        message: format!("Retrieved {} tables", tables.len()),
+        data: tables,
+    })
+}
+
+// API endpoint to create a table, with a schema if one is given
+async fn create_table(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req: web::Json<CreateTableRequest>,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match databases.get_mut(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+
+    if db.is_read_only() {
+        return read_only_response(&db_name);
+    }
+
+    let schema = match req.schema.as_deref().map(str::parse::<Schema>).transpose() {
+        Ok(schema) => schema,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message,
+                data: None,
+            });
+        }
+    };
+
+    match db.create_table(&req.name, schema) {
+        Ok(()) => HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            message: format!("Created table '{}' in database '{}'", req.name, db_name),
+            data: None,
+        }),
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            HttpResponse::Conflict().json(ApiResponse {
+                success: false,
+                message: error.to_string(),
+                data: None,
+            })
+        }
+        Err(error) if is_client_error(&error) => HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: error.to_string(),
+            data: None,
+        }),
+        Err(error) => HttpResponse::InternalServerError().json(ApiResponse {
+            success: false,
+            message: format!("Failed to create table: {}", error),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to drop a table and delete its records
+async fn drop_table(
+    data: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let (db_name, table) = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match databases.get_mut(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+
+    if db.is_read_only() {
+        return read_only_response(&db_name);
+    }
+
+    match db.drop_table(&table) {
+        Ok(()) => HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            message: format!("Dropped table '{}' from database '{}'", table, db_name),
+            data: None,
+        }),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: not_found_message(&db_name, Some(&table)),
+                data: None,
+            })
+        }
+        Err(error) if is_client_error(&error) => HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: error.to_string(),
+            data: None,
+        }),
+        Err(error) => HttpResponse::InternalServerError().json(ApiResponse {
+            success: false,
+            message: format!("Failed to drop table: {}", error),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to run a query such as
+// SELECT * WHERE key BETWEEN 1 AND 10 ORDER BY value LIMIT 5
+async fn run_query(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req: web::Json<QueryRequest>,
+) -> impl Responder {
+    let db_name = path.into_inner();
     let mut databases = data.databases.lock().unwrap();
     
-    if let Some(tree) = databases.get_mut(&db_name) {
-        let file_path = format!("{}.db", db_name);
-        
-        // Try to delete the record
-        let deleted = tree.delete(key);
-        
-        if deleted {
-            // Save changes to disk
-            match save_records(&file_path, &tree.get_all_records()) {
-                Ok(_) => {
-                    HttpResponse::Ok().json(ApiResponse {
-                        success: true,
-                        message: format!("Deleted record with key {}", key),
-                        data: None,
-                    })
-                }
-                Err(error) => {
-                    HttpResponse::InternalServerError().json(ApiResponse {
-                        success: false,
-                        message: format!("Failed to save changes: {}", error),
-                        data: None,
-                    })
+    let db = match databases.get_mut(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+    
+    if let Err(error) = reload_if_changed(&db_name, db) {
+        return reload_failed_response(&db_name, error);
+    }
+    
+    let result = query::parse(&req.query).and_then(|statement| {
+        let statement = if req.explain {
+            Statement::Explain(Box::new(statement))
+        } else {
+            statement
+        };
+        execute_in(&mut databases, &db_name, &statement)
+    });
+    query_response(&db_name, result)
+}
+
+// Run a statement against a connected database. Joins can also read the other
+// connected databases, which are reloaded first if they changed on disk.
+fn execute_in(
+    databases: &mut HashMap<String, Database>,
+    db_name: &str,
+    statement: &Statement,
+) -> Result<QueryOutput, QueryError> {
+    let join = match statement {
+        Statement::Join(join) => join,
+        _ => match databases.get_mut(db_name) {
+            Some(db) => return query::execute(db, statement),
+            None => return Err(QueryError::Invalid(format!("Database '{}' not found", db_name))),
+        },
+    };
+
+    for source in [&join.left, &join.right] {
+        let name = source.database.as_deref().unwrap_or(db_name);
+        if let Some(db) = databases.get_mut(name) {
+            reload_if_changed(name, db)?;
+        }
+    }
+    match databases.get(db_name) {
+        Some(db) => query::join(db, |name| databases.get(name), join),
+        None => Err(QueryError::Invalid(format!("Database '{}' not found", db_name))),
+    }
+}
+
+// API endpoint to pair the records of a database or table with those of
+// another one by key, e.g. GET /api/db/users/join?with=shop.orders&type=left
+async fn join_records(
+    data: web::Data<AppState>,
+    path: web::Path<TablePath>,
+    query: web::Query<JoinQuery>,
+) -> impl Responder {
+    let TablePath { db_name, table } = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    if find_table(&mut databases, &db_name, table.as_deref()).is_none() {
+        return HttpResponse::NotFound().json(ApiResponse {
+            success: false,
+            message: not_found_message(&db_name, table.as_deref()),
+            data: None,
+        });
+    }
+
+    let kind = match query.kind.as_deref() {
+        None | Some("inner") => JoinKind::Inner,
+        Some("left") => JoinKind::Left,
+        Some(kind) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: format!("Unknown join type '{}', expected inner or left", kind),
+                data: None,
+            });
+        }
+    };
+    let filter = match key_range(None, &query.from, &query.to) {
+        Ok(filter) => filter,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message,
+                data: None,
+            });
+        }
+    };
+
+    let right = match query.with.split_once('.') {
+        Some((database, table)) => Source {
+            database: Some(database.to_string()),
+            table: table.to_string(),
+        },
+        None => Source {
+            database: None,
+            table: query.with.clone(),
+        },
+    };
+    let join = Statement::Join(Join {
+        kind,
+        left: Source {
+            database: Some(db_name.clone()),
+            table: table.unwrap_or_else(|| MAIN_TABLE.to_string()),
+        },
+        right,
+        select: Select {
+            table: None,
+            columns: Vec::new(),
+            filter,
+            order_by: None,
+            limit: None,
+            offset: 0,
+        },
+    });
+    query_response(&db_name, execute_in(&mut databases, &db_name, &join))
+}
+
+// Response for the outcome of a query: the rows it selected, or a summary of
+// what it changed
+fn query_response(db_name: &str, result: Result<QueryOutput, QueryError>) -> HttpResponse {
+    match result {
+        Ok(output) => {
+            let message = output.summary();
+            let (columns, rows, explain) = match output {
+                QueryOutput::Rows { columns, rows } => {
+                    let objects = rows
+                        .into_iter()
+                        .map(|row| {
+                            columns
+                                .iter()
+                                .cloned()
+                                .zip(row.into_iter().map(|cell| serde_json::to_value(cell).unwrap_or_default()))
+                                .collect()
+                        })
+                        .collect();
+                    (Some(columns), Some(objects), None)
                 }
+                QueryOutput::Explain(explain) => (None, None, Some(explain)),
+                QueryOutput::Changed { .. } | QueryOutput::Done(_) => (None, None, None),
+            };
+            
+            HttpResponse::Ok().json(QueryResponse {
+                success: true,
+                message,
+                columns,
+                data: rows,
+                explain,
+            })
+        }
+        Err(QueryError::Io(error)) if error.kind() == io::ErrorKind::PermissionDenied => {
+            read_only_response(db_name)
+        }
+        Err(QueryError::Io(error)) if is_client_error(&error) => {
+            HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: error.to_string(),
+                data: None,
+            })
+        }
+        Err(QueryError::Io(error)) => {
+            HttpResponse::InternalServerError().json(ApiResponse {
+                success: false,
+                message: format!("Failed to run query: {}", error),
+                data: None,
+            })
+        }
+        Err(error) => {
+            HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: error.to_string(),
+                data: None,
+            })
+        }
+    }
+}
+
+// API endpoint to prepare a statement with ? placeholders, returning its id.
+// Fails with 429 while MAX_STATEMENTS are prepared and not closed.
+async fn prepare_statement(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    req: web::Json<QueryRequest>,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let mut databases = data.databases.lock().unwrap();
+
+    let db = match databases.get_mut(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+
+    if let Err(error) = reload_if_changed(&db_name, db) {
+        return reload_failed_response(&db_name, error);
+    }
+
+    match query::prepare(db, &req.query) {
+        Ok(prepared) => {
+            let mut statements = data.statements.lock().unwrap();
+            if statements.len() >= MAX_STATEMENTS {
+                return HttpResponse::TooManyRequests().json(ApiResponse {
+                    success: false,
+                    message: format!(
+                        "{} statements are prepared already, close some with DELETE /api/db/{{db}}/statements/{{id}}",
+                        MAX_STATEMENTS
+                    ),
+                    data: None,
+                });
             }
-        } else {
-            HttpResponse::NotFound().json(ApiResponse {
+            let id = data.next_statement.fetch_add(1, Ordering::Relaxed);
+            let params = prepared.params();
+            statements.insert(id, (db_name, prepared));
+
+            HttpResponse::Ok().json(PrepareResponse {
+                success: true,
+                message: format!("Prepared statement {} with {} parameters", id, params),
+                id,
+                params,
+            })
+        }
+        Err(error) => HttpResponse::BadRequest().json(ApiResponse {
+            success: false,
+            message: error.to_string(),
+            data: None,
+        }),
+    }
+}
+
+// API endpoint to run a prepared statement, body {"params": [41, "ann"]}
+async fn execute_statement(
+    data: web::Data<AppState>,
+    path: web::Path<(String, u64)>,
+    req: web::Json<ExecuteRequest>,
+) -> impl Responder {
+    let (db_name, id) = path.into_inner();
+    let prepared = match data.statements.lock().unwrap().get(&id) {
+        Some((name, prepared)) if *name == db_name => prepared.clone(),
+        _ => {
+            return HttpResponse::NotFound().json(ApiResponse {
                 success: false,
-                message: format!("Record with key {} not found", key),
+                message: format!("Statement {} not found in database '{}'", id, db_name),
+                data: None,
+            });
+        }
+    };
+
+    let params = match req
+        .params
+        .iter()
+        .map(value_from_json)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(params) => params,
+        Err(json) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                success: false,
+                message: format!("Parameter {} is not a number, string, boolean or null", json),
+                data: None,
+            });
+        }
+    };
+
+    let mut databases = data.databases.lock().unwrap();
+    let db = match databases.get_mut(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+
+    if let Err(error) = reload_if_changed(&db_name, db) {
+        return reload_failed_response(&db_name, error);
+    }
+
+    let result = prepared
+        .bind(&params)
+        .and_then(|statement| execute_in(&mut databases, &db_name, &statement));
+    query_response(&db_name, result)
+}
+
+// API endpoint to forget a prepared statement
+async fn close_statement(data: web::Data<AppState>, path: web::Path<(String, u64)>) -> impl Responder {
+    let (db_name, id) = path.into_inner();
+    let mut statements = data.statements.lock().unwrap();
+    match statements.get(&id) {
+        Some((name, _)) if *name == db_name => {
+            statements.remove(&id);
+            HttpResponse::Ok().json(ApiResponse {
+                success: true,
+                message: format!("Closed statement {}", id),
                 data: None,
             })
         }
-    } else {
-        HttpResponse::NotFound().json(ApiResponse {
+        _ => HttpResponse::NotFound().json(ApiResponse {
             success: false,
-            message: format!("Database '{}' not found", db_name),
+            message: format!("Statement {} not found in database '{}'", id, db_name),
             data: None,
+        }),
+    }
+}
+
+// Format a change as a Server-Sent Event
+fn change_event(change: &Change) -> web::Bytes {
+    let dto = ChangeDto::from(change);
+    let json = serde_json::to_string(&dto).unwrap_or_default();
+    web::Bytes::from(format!("id: {}\nevent: {}\ndata: {}\n\n", dto.seq, dto.kind, json))
+}
+
+// Whether a change is to the table being watched, if only one is
+fn watches(table: &Option<String>, change: &Change) -> bool {
+    table.as_ref().is_none_or(|table| *table == change.table)
+}
+
+// Tell the client it missed changes and should reload all records
+fn reset_event(last_seq: u64) -> web::Bytes {
+    web::Bytes::from(format!("id: {}\nevent: reset\ndata: {{\"seq\":{}}}\n\n", last_seq, last_seq))
+}
+
+// API endpoint streaming inserts, updates and deletes as Server-Sent Events,
+// those of every table unless ?table=<name> picks one. Clients resume with
+// ?since=<seq> or the Last-Event-ID header; if the changes since then are no
+// longer kept a "reset" event is sent instead.
+async fn watch_changes(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ChangesQuery>,
+    http_req: HttpRequest,
+) -> impl Responder {
+    let db_name = path.into_inner();
+    let table = query.table.clone();
+    let databases = data.databases.lock().unwrap();
+    
+    let db = match databases.get(&db_name) {
+        Some(db) => db,
+        None => {
+            return HttpResponse::NotFound().json(ApiResponse {
+                success: false,
+                message: format!("Database '{}' not found", db_name),
+                data: None,
+            });
+        }
+    };
+    
+    let last_event_id = http_req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    
+    // Subscribe while still holding the lock so no change falls between the
+    // backlog and the live stream
+    let receiver = data.changes.subscribe();
+    let mut pending = VecDeque::new();
+    let last_seq = match query.since.or(last_event_id) {
+        Some(since) => match db.changes_since(since) {
+            Some(changes) => {
+                let changes = changes.iter().filter(|change| watches(&table, change));
+                pending.extend(changes.map(change_event));
+                db.last_seq()
+            }
+            None => {
+                pending.push_back(reset_event(db.last_seq()));
+                db.last_seq()
+            }
+        },
+        None => db.last_seq(),
+    };
+    drop(databases);
+    
+    let events = stream::unfold(
+        (pending, receiver, db_name, table, last_seq),
+        |(mut pending, mut receiver, db_name, table, mut last_seq)| async move {
+            if let Some(event) = pending.pop_front() {
+                return Some((Ok::<_, actix_web::Error>(event), (pending, receiver, db_name, table, last_seq)));
+            }
+            
+            loop {
+                let event = match tokio::time::timeout(KEEP_ALIVE_INTERVAL, receiver.recv()).await {
+                    Ok(Ok((name, change))) if name == db_name && change.seq > last_seq => {
+                        last_seq = change.seq;
+                        if !watches(&table, &change) {
+                            continue;
+                        }
+                        change_event(&change)
+                    }
+                    Ok(Ok(_)) => continue,
+                    Ok(Err(broadcast::error::RecvError::Lagged(_))) => reset_event(last_seq),
+                    Ok(Err(broadcast::error::RecvError::Closed)) => return None,
+                    Err(_) => web::Bytes::from_static(b": keep-alive\n\n"),
+                };
+                return Some((Ok(event), (pending, receiver, db_name, table, last_seq)));
+            }
+        },
+    );
+    
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events)
+}
+
+// Periodically reload databases changed by other programs, so that change
+// streams pick up their edits without waiting for a request
+async fn watch_files(data: web::Data<AppState>) {
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        // Reading the files blocks, so it runs on the blocking thread pool
+        // rather than on a worker that serves requests
+        let data = data.clone();
+        let watched = web::block(move || {
+            let mut databases = data.databases.lock().unwrap();
+            for (db_name, db) in databases.iter_mut() {
+                if let Err(error) = reload_if_changed(db_name, db) {
+                    eprintln!("Failed to reload database '{}': {}", db_name, error);
+                }
+            }
         })
+        .await;
+        if let Err(error) = watched {
+            eprintln!("Failed to check the databases for changes: {}", error);
+        }
+    }
+}
+
+// Periodically delete expired records and save the result
+async fn sweep_expired(data: web::Data<AppState>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        // Saving blocks, so it runs on the blocking thread pool as in watch_files
+        let data = data.clone();
+        let swept = web::block(move || {
+            let mut databases = data.databases.lock().unwrap();
+            for (db_name, db) in databases.iter_mut() {
+                if db.is_read_only() {
+                    continue;
+                }
+                match db.purge_expired() {
+                    Ok(0) => {}
+                    Ok(count) => println!("Removed {} expired records from {}", count, db_name),
+                    Err(error) => eprintln!("Failed to remove expired records from {}: {}", db_name, error),
+                }
+            }
+        })
+        .await;
+        if let Err(error) = swept {
+            eprintln!("Failed to remove expired records: {}", error);
+        }
     }
 }
 
 // Main function to start the web server
-pub async fn start_server() -> io::Result<()> {
-    println!("Starting B-Tree database web server...");
-    println!("Open your browser and navigate to: http://localhost:8080");
+pub async fn start_server(config: Config) -> io::Result<()> {
+    let address = (config.bind.clone(), config.port);
+    let static_dir = config.static_dir.clone();
+    println!("Starting B-Tree database web server (order {})...", btree::order());
+    println!("Open your browser and navigate to: http://{}:{}", config.bind, config.port);
     
     // Create the app state with an empty map of databases
+    let (changes, _) = broadcast::channel(1024);
     let app_state = web::Data::new(AppState {
         databases: Mutex::new(HashMap::new()),
+        changes,
+        statements: Mutex::new(HashMap::new()),
+        next_statement: AtomicU64::new(1),
+        config,
     });
     
+    // Connect to the database named by --db before serving requests
+    if let Some(db_name) = app_state.config.db.clone() {
+        let db = open_database(&app_state, &db_name, false)?;
+        println!("Connected to database: {}{}", db_name, if db.is_read_only() { " (read-only)" } else { "" });
+        app_state.databases.lock().unwrap().insert(db_name, db);
+    }
+    
+    actix_web::rt::spawn(watch_files(app_state.clone()));
+    actix_web::rt::spawn(sweep_expired(app_state.clone()));
+    
     // Start the HTTP server
     HttpServer::new(move || {
         // Configure CORS to allow frontend access
@@ -306,13 +1965,41 @@ pub async fn start_server() -> io::Result<()> {
                     .route("/db/{db_name}/records", web::get().to(get_all_records))
                     .route("/db/{db_name}/records/{key}", web::get().to(find_record))
                     .route("/db/{db_name}/records", web::post().to(insert_record))
+                    .route("/db/{db_name}/records/{key}", web::put().to(upsert_record))
+                    .route("/db/{db_name}/records/{key}", web::patch().to(update_record))
                     .route("/db/{db_name}/records/{key}", web::delete().to(delete_record))
+                    .route("/db/{db_name}/records/{key}/history", web::get().to(record_history))
+                    .route("/db/{db_name}/changes", web::get().to(watch_changes))
+                    .route("/db/{db_name}/query", web::post().to(run_query))
+                    .route("/db/{db_name}/statements", web::post().to(prepare_statement))
+                    .route("/db/{db_name}/statements/{id}", web::post().to(execute_statement))
+                    .route("/db/{db_name}/statements/{id}", web::delete().to(close_statement))
+                    .route("/db/{db_name}/aggregate", web::get().to(aggregate_records))
+                    .route("/db/{db_name}/search", web::get().to(search_records))
+                    .route("/db/{db_name}/join", web::get().to(join_records))
+                    .route("/db/{db_name}/schema", web::get().to(get_schema))
+                    .route("/db/{db_name}/schema", web::put().to(set_schema))
+                    .route("/db/{db_name}/tables", web::get().to(list_tables))
+                    .route("/db/{db_name}/tables", web::post().to(create_table))
+                    .route("/db/{db_name}/tables/{table}", web::delete().to(drop_table))
+                    .route("/db/{db_name}/tables/{table}/records", web::get().to(get_all_records))
+                    .route("/db/{db_name}/tables/{table}/records", web::post().to(insert_record))
+                    .route("/db/{db_name}/tables/{table}/records/{key}", web::get().to(find_record))
+                    .route("/db/{db_name}/tables/{table}/records/{key}", web::put().to(upsert_record))
+                    .route("/db/{db_name}/tables/{table}/records/{key}", web::patch().to(update_record))
+                    .route("/db/{db_name}/tables/{table}/records/{key}", web::delete().to(delete_record))
+                    .route("/db/{db_name}/tables/{table}/records/{key}/history", web::get().to(record_history))
+                    .route("/db/{db_name}/tables/{table}/aggregate", web::get().to(aggregate_records))
+                    .route("/db/{db_name}/tables/{table}/search", web::get().to(search_records))
+                    .route("/db/{db_name}/tables/{table}/join", web::get().to(join_records))
+                    .route("/db/{db_name}/tables/{table}/schema", web::get().to(get_schema))
+                    .route("/db/{db_name}/tables/{table}/schema", web::put().to(set_schema))
             )
             // Static files
-            .service(fs::Files::new("/static", "./static").show_files_listing())
+            .service(fs::Files::new("/static", static_dir.clone()).show_files_listing())
             .default_service(web::get().to(index))
     })
-    .bind("127.0.0.1:8080")?
+    .bind(address)?
     .run()
     .await
 }
\ No newline at end of file